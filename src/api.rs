@@ -0,0 +1,235 @@
+use crate::{
+    index::ImageEntry, sort::SortMode, tag_counts, urlencode_path, Gallery, Paging, ScreenFit, Sorting, TagFilter,
+    CARD_WIDTH,
+};
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use axum_extra::extract::{Query, QueryRejection};
+use rand::seq::SliceRandom;
+use serde::Serialize;
+use std::sync::Arc;
+
+#[derive(Serialize)]
+pub struct ImageJson {
+    name: String,
+    url: String,
+    thumb_url: String,
+    tags: Vec<String>,
+    width: Option<u32>,
+    height: Option<u32>,
+    /// MP4 or WebM rather than a picture
+    video: bool,
+    /// A GIF, APNG or WebP with more than one frame
+    animated: bool,
+    /// File size in bytes
+    size: u64,
+    /// Last modification, in seconds since the Unix epoch
+    mtime: Option<u64>,
+    /// Dominant colour as `#rrggbb`, once known
+    color: Option<String>,
+    /// Tiny blurry preview as a `data:` URI, once known
+    preview: Option<String>,
+    /// From the image's sidecar file
+    title: Option<String>,
+    artist: Option<String>,
+    source: Option<String>,
+    license: Option<String>,
+    alt: Option<String>,
+    /// Embedded in the file (EXIF, XMP, IPTC)
+    camera: Option<String>,
+    taken: Option<String>,
+    copyright: Option<String>,
+    description: Option<String>,
+    keywords: Vec<String>,
+}
+
+#[derive(Serialize)]
+pub struct ImagesJson {
+    /// Number of matching images, across all pages
+    total: usize,
+    /// Only set when a page was asked for
+    #[serde(skip_serializing_if = "Option::is_none")]
+    page: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    per_page: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pages: Option<usize>,
+    /// Seed of a random order, to pass back for the next pages
+    #[serde(skip_serializing_if = "Option::is_none")]
+    seed: Option<u64>,
+    images: Vec<ImageJson>,
+}
+
+#[derive(Serialize)]
+pub struct TagJson {
+    name: String,
+    count: usize,
+}
+
+#[derive(Serialize)]
+pub struct TagsJson {
+    tags: Vec<TagJson>,
+}
+
+/// Error body shared by every endpoint: `{"error": {"status": 404, "message": "..."}}`.
+pub struct ApiError {
+    status: StatusCode,
+    message: String,
+}
+
+#[derive(Serialize)]
+struct ErrorBody<'a> {
+    error: ErrorDetail<'a>,
+}
+
+#[derive(Serialize)]
+struct ErrorDetail<'a> {
+    status: u16,
+    message: &'a str,
+}
+
+impl ApiError {
+    pub fn new(status: StatusCode, message: impl Into<String>) -> Self {
+        ApiError {
+            status,
+            message: message.into(),
+        }
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let body = ErrorBody {
+            error: ErrorDetail {
+                status: self.status.as_u16(),
+                message: &self.message,
+            },
+        };
+        (self.status, Json(body)).into_response()
+    }
+}
+
+impl From<QueryRejection> for ApiError {
+    fn from(e: QueryRejection) -> Self {
+        ApiError::new(StatusCode::BAD_REQUEST, e.to_string())
+    }
+}
+
+/// `GET /api/images`: every image, optionally filtered by `?tag=` and `?match=`.
+/// Sorted by `?sort=` (plus `?seed=` for a repeatable random order), and with
+/// `?page=` or `?per_page=` only that page is returned.
+pub async fn images(
+    State(gallery): State<Arc<Gallery>>,
+    filter: Result<Query<TagFilter>, QueryRejection>,
+    sorting: Result<Query<Sorting>, QueryRejection>,
+    paging: Result<Query<Paging>, QueryRejection>,
+) -> Result<Json<ImagesJson>, ApiError> {
+    let Query(filter) = filter?;
+    let Query(sorting) = sorting?;
+    let Query(paging) = paging?;
+    let snapshot = gallery.index.snapshot();
+    let mut matching: Vec<&Arc<ImageEntry>> = snapshot
+        .values()
+        .filter(|img| filter.mode.matches(&img.tags, &filter.tag))
+        .collect();
+    let seed = sorting.seed();
+    sorting.sort.sort(&mut matching, seed);
+    let seed = (sorting.sort == SortMode::Random).then_some(seed);
+    let total = matching.len();
+    if !paging.is_set() {
+        return Ok(Json(ImagesJson {
+            total,
+            page: None,
+            per_page: None,
+            pages: None,
+            seed,
+            images: matching.into_iter().map(|img| describe(&gallery, img)).collect(),
+        }));
+    }
+    let page = paging.page();
+    Ok(Json(ImagesJson {
+        total,
+        page: Some(page),
+        per_page: Some(paging.per_page()),
+        pages: Some(paging.pages(total)),
+        seed,
+        images: paging.slice(&matching, page).iter().map(|img| describe(&gallery, img)).collect(),
+    }))
+}
+
+/// `GET /api/tags`: tags that have at least one image, with image counts.
+pub async fn tags(State(gallery): State<Arc<Gallery>>) -> Json<TagsJson> {
+    let images = gallery.index.snapshot();
+    let tags = tag_counts(&gallery.rules, images.values())
+        .into_iter()
+        .map(|(name, count)| TagJson {
+            name: name.to_string(),
+            count,
+        })
+        .collect();
+    Json(TagsJson { tags })
+}
+
+/// `GET /api/random`: one random image, honouring the same filters as
+/// `/api/images` plus `?fit=WIDTHxHEIGHT`.
+pub async fn random(
+    State(gallery): State<Arc<Gallery>>,
+    filter: Result<Query<TagFilter>, QueryRejection>,
+    fit: Result<Query<ScreenFit>, QueryRejection>,
+) -> Result<Json<ImageJson>, ApiError> {
+    let Query(filter) = filter?;
+    let Query(fit) = fit?;
+    let images = gallery.index.snapshot();
+    let candidates: Vec<&Arc<ImageEntry>> = images
+        .values()
+        .filter(|img| filter.mode.matches(&img.tags, &filter.tag))
+        .filter(|img| fit.matches(img))
+        .collect();
+    match candidates.choose(&mut rand::thread_rng()) {
+        Some(choice) => Ok(Json(describe(&gallery, choice))),
+        None => Err(ApiError::new(StatusCode::NOT_FOUND, "no images match")),
+    }
+}
+
+/// Fallback for unknown `/api/...` paths, so clients always get JSON back.
+pub async fn not_found() -> ApiError {
+    ApiError::new(StatusCode::NOT_FOUND, "no such endpoint")
+}
+
+fn describe(gallery: &Gallery, img: &ImageEntry) -> ImageJson {
+    let encoded = urlencode_path(&img.name);
+    let sidecar = img.sidecar.clone().unwrap_or_default();
+    let embedded = img.embedded.clone().unwrap_or_default();
+    ImageJson {
+        name: img.name.clone(),
+        url: format!("{}/wallpapers/{encoded}", gallery.prefix),
+        thumb_url: format!(
+            "{}/thumbs/{}/{encoded}",
+            gallery.prefix,
+            gallery.thumbs.size_for(CARD_WIDTH)
+        ),
+        tags: img.tags.clone(),
+        width: img.width,
+        height: img.height,
+        video: img.format.is_video(),
+        animated: img.animated,
+        size: img.size,
+        mtime: img.mtime,
+        color: img.placeholder.as_ref().and_then(|p| p.color.clone()),
+        preview: img.placeholder.as_ref().and_then(|p| p.preview.clone()),
+        title: sidecar.title,
+        artist: sidecar.artist.or(embedded.artist),
+        source: sidecar.source,
+        license: sidecar.license,
+        alt: sidecar.alt,
+        camera: embedded.camera,
+        taken: embedded.taken,
+        copyright: embedded.copyright,
+        description: embedded.description,
+        keywords: embedded.keywords,
+    }
+}
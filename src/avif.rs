@@ -0,0 +1,358 @@
+use image::{
+    error::{DecodingError, ImageFormatHint},
+    DynamicImage, ImageError, ImageFormat, ImageResult, RgbImage, RgbaImage,
+};
+use rav1d::{
+    include::dav1d::{
+        data::Dav1dData,
+        dav1d::{Dav1dContext, Dav1dSettings},
+        headers::{
+            Dav1dMatrixCoefficients, DAV1D_MC_BT2020_CL, DAV1D_MC_BT2020_NCL, DAV1D_MC_BT709, DAV1D_MC_FCC,
+            DAV1D_MC_IDENTITY, DAV1D_MC_SMPTE240, DAV1D_PIXEL_LAYOUT_I400, DAV1D_PIXEL_LAYOUT_I420,
+            DAV1D_PIXEL_LAYOUT_I422,
+        },
+        picture::Dav1dPicture,
+    },
+    src::lib::{
+        dav1d_close, dav1d_data_create, dav1d_data_unref, dav1d_default_settings, dav1d_get_picture, dav1d_open,
+        dav1d_picture_unref, dav1d_send_data,
+    },
+    Dav1dResult,
+};
+use std::{io, mem::MaybeUninit, ptr::NonNull};
+
+/// Largest frame decoded, in pixels; bigger ones are refused before any
+/// memory is taken for them
+const MAX_PIXELS: u32 = 1 << 28;
+
+/// Decodes the primary image of an AVIF file, with its alpha channel if it
+/// has one. 10 and 12 bit images come out as 8 bit, which is all the
+/// thumbnails and renders made from them keep anyway. Grids of tiles are
+/// not supported.
+pub fn decode(mut data: &[u8]) -> ImageResult<DynamicImage> {
+    let avif = avif_parse::read_avif(&mut data).map_err(|e| error(e.to_string()))?;
+    let (width, height, mut pixels) = with_picture(&avif.primary_item, |picture| {
+        (picture.width, picture.height, picture.to_rgb())
+    })?;
+    let alpha = match &avif.alpha_item {
+        Some(item) => with_picture(item, |picture| {
+            ((picture.width, picture.height) == (width, height)).then(|| picture.to_gray())
+        })?,
+        None => None,
+    };
+    let (width, height) = (width as u32, height as u32);
+    let image = match alpha {
+        None => RgbImage::from_raw(width, height, pixels).map(DynamicImage::ImageRgb8),
+        Some(alpha) => {
+            if avif.premultiplied_alpha {
+                for (rgb, &a) in pixels.chunks_exact_mut(3).zip(&alpha) {
+                    for c in rgb {
+                        *c = if a == 0 { 0 } else { (u32::from(*c) * 255 / u32::from(a)).min(255) as u8 };
+                    }
+                }
+            }
+            let rgba = pixels.chunks_exact(3).zip(alpha).flat_map(|(rgb, a)| [rgb[0], rgb[1], rgb[2], a]);
+            RgbaImage::from_raw(width, height, rgba.collect()).map(DynamicImage::ImageRgba8)
+        }
+    };
+    image.ok_or_else(|| error("picture smaller than its size"))
+}
+
+fn error(message: impl Into<String>) -> ImageError {
+    ImageError::Decoding(DecodingError::new(ImageFormatHint::Exact(ImageFormat::Avif), message.into()))
+}
+
+fn check(result: Dav1dResult, what: &str) -> ImageResult<()> {
+    if result.0 < 0 {
+        return Err(error(format!("{what}: {}", io::Error::from_raw_os_error(-result.0))));
+    }
+    Ok(())
+}
+
+/// Decodes the one frame in `obu` (AV1 data, as stored in an AVIF item) and
+/// hands it to `read`.
+fn with_picture<T>(obu: &[u8], read: impl FnOnce(&Picture) -> T) -> ImageResult<T> {
+    let mut settings = MaybeUninit::<Dav1dSettings>::uninit();
+    // SAFETY: dav1d_default_settings writes every field
+    let mut settings = unsafe {
+        dav1d_default_settings(NonNull::from(&mut settings).cast());
+        settings.assume_init()
+    };
+    // One still picture: no threads to start and no frames to hold back
+    settings.n_threads = 1;
+    settings.max_frame_delay = 1;
+    settings.frame_size_limit = MAX_PIXELS;
+
+    let mut decoder = Decoder(None);
+    // SAFETY: both pointers are to live values
+    let opened = unsafe { dav1d_open(Some(NonNull::from(&mut decoder.0)), Some(NonNull::from(&mut settings))) };
+    check(opened, "cannot start the AV1 decoder")?;
+    let Some(context) = decoder.0 else {
+        return Err(error("cannot start the AV1 decoder"));
+    };
+
+    let mut data = Data(Dav1dData::default());
+    // SAFETY: `data` is a live value; the returned buffer is `obu.len()`
+    // bytes long, or null
+    let buf = unsafe { dav1d_data_create(Some(NonNull::from(&mut data.0)), obu.len()) };
+    if buf.is_null() {
+        return Err(error("no AV1 data"));
+    }
+    // SAFETY: `buf` was just allocated with room for `obu`
+    unsafe { std::ptr::copy_nonoverlapping(obu.as_ptr(), buf, obu.len()) };
+
+    let again = Dav1dResult(-libc::EAGAIN);
+    let mut picture = PictureRef(Dav1dPicture::default());
+    loop {
+        let sent = match data.0.sz {
+            0 => Dav1dResult(0),
+            // SAFETY: the decoder is open and `data` came from dav1d_data_create
+            _ => unsafe { dav1d_send_data(Some(context), Some(NonNull::from(&mut data.0))) },
+        };
+        if sent != again {
+            check(sent, "broken AV1 data")?;
+        }
+        // SAFETY: the decoder is open and `picture` is a live value
+        let got = unsafe { dav1d_get_picture(Some(context), Some(NonNull::from(&mut picture.0))) };
+        if got.0 == 0 {
+            break;
+        }
+        if got != again {
+            check(got, "broken AV1 data")?;
+        }
+        if sent == again || data.0.sz == 0 {
+            return Err(error("no picture in the AV1 data"));
+        }
+    }
+
+    // SAFETY: the picture was just handed out by the decoder and lives until
+    // `picture` is dropped, after `view` is last used
+    let view = unsafe { Picture::of(&picture.0) }.ok_or_else(|| error("unexpected AV1 picture layout"))?;
+    Ok(read(&view))
+}
+
+/// Closes the decoder when dropped.
+struct Decoder(Option<Dav1dContext>);
+
+impl Drop for Decoder {
+    fn drop(&mut self) {
+        // SAFETY: the context came from dav1d_open, or is None
+        unsafe { dav1d_close(Some(NonNull::from(&mut self.0))) }
+    }
+}
+
+/// Frees what the decoder didn't take of the data when dropped.
+struct Data(Dav1dData);
+
+impl Drop for Data {
+    fn drop(&mut self) {
+        // SAFETY: the data came from dav1d_data_create, or is empty
+        unsafe { dav1d_data_unref(Some(NonNull::from(&mut self.0))) }
+    }
+}
+
+/// Gives the picture back to the decoder when dropped.
+struct PictureRef(Dav1dPicture);
+
+impl Drop for PictureRef {
+    fn drop(&mut self) {
+        // SAFETY: the picture came from dav1d_get_picture, or is empty
+        unsafe { dav1d_picture_unref(Some(NonNull::from(&mut self.0))) }
+    }
+}
+
+/// The samples of a decoded picture.
+struct Picture<'a> {
+    width: usize,
+    height: usize,
+    /// Bits per sample: 8, 10 or 12, stored in two bytes above 8
+    depth: u32,
+    /// Chroma subsampling as shifts, (1, 1) for 4:2:0
+    shift: (usize, usize),
+    grayscale: bool,
+    matrix: Dav1dMatrixCoefficients,
+    full_range: bool,
+    /// Y, U and V, row after row `strides` bytes apart; U and V are empty
+    /// for grayscale pictures
+    planes: [&'a [u8]; 3],
+    strides: [usize; 2],
+}
+
+impl<'a> Picture<'a> {
+    /// # Safety
+    ///
+    /// `picture` must come from dav1d_get_picture and not be unreferenced
+    /// while the view lives.
+    unsafe fn of(picture: &'a Dav1dPicture) -> Option<Self> {
+        let p = &picture.p;
+        // SAFETY: the sequence header lives as long as the picture
+        let header = unsafe { picture.seq_hdr?.as_ref() };
+        let (width, height) = (usize::try_from(p.w).ok()?, usize::try_from(p.h).ok()?);
+        let strides = [usize::try_from(picture.stride[0]).ok()?, usize::try_from(picture.stride[1]).ok()?];
+        let depth = u32::try_from(p.bpc).ok().filter(|d| matches!(d, 8 | 10 | 12))?;
+        let shift = match p.layout {
+            DAV1D_PIXEL_LAYOUT_I420 => (1, 1),
+            DAV1D_PIXEL_LAYOUT_I422 => (1, 0),
+            _ => (0, 0),
+        };
+        let grayscale = p.layout == DAV1D_PIXEL_LAYOUT_I400;
+        let sample_size = if depth > 8 { 2 } else { 1 };
+        if width == 0 || height == 0 || strides[0] < width * sample_size {
+            return None;
+        }
+        // SAFETY: the decoder allocates `stride` bytes for every row of a plane
+        let plane = |i: usize, stride: usize, rows: usize| unsafe {
+            Some(std::slice::from_raw_parts(picture.data[i]?.as_ptr().cast::<u8>(), stride * rows))
+        };
+        let luma = plane(0, strides[0], height)?;
+        let chroma_rows = (height + shift.1) >> shift.1;
+        let (u, v) = if grayscale {
+            (&[][..], &[][..])
+        } else {
+            if strides[1] < ((width + shift.0) >> shift.0) * sample_size {
+                return None;
+            }
+            (plane(1, strides[1], chroma_rows)?, plane(2, strides[1], chroma_rows)?)
+        };
+        Some(Picture {
+            width,
+            height,
+            depth,
+            shift,
+            grayscale,
+            matrix: header.mtrx,
+            full_range: header.color_range != 0,
+            planes: [luma, u, v],
+            strides,
+        })
+    }
+
+    /// Sample `x`, `y` of `plane`, in that plane's own (subsampled) pixels.
+    fn sample(&self, plane: usize, x: usize, y: usize) -> f32 {
+        let row = &self.planes[plane][y * self.strides[plane.min(1)]..];
+        let sample = if self.depth > 8 { u16::from_ne_bytes([row[2 * x], row[2 * x + 1]]) } else { u16::from(row[x]) };
+        f32::from(sample)
+    }
+
+    /// Offset and scale that bring luma samples to 0..1, and the scale of
+    /// chroma samples around the middle.
+    fn ranges(&self) -> (f32, f32, f32) {
+        let max = ((1u32 << self.depth) - 1) as f32;
+        if self.full_range {
+            (0.0, max, max)
+        } else {
+            let step = (1u32 << (self.depth - 8)) as f32;
+            (16.0 * step, 219.0 * step, 224.0 * step)
+        }
+    }
+
+    /// The luma plane alone, e.g. of an alpha channel, as 8 bit samples.
+    fn to_gray(&self) -> Vec<u8> {
+        let (offset, scale, _) = self.ranges();
+        let mut gray = Vec::with_capacity(self.width * self.height);
+        for y in 0..self.height {
+            for x in 0..self.width {
+                gray.push(to_8bit((self.sample(0, x, y) - offset) / scale));
+            }
+        }
+        gray
+    }
+
+    /// 8 bit RGB samples, converting from YUV as the picture's matrix says.
+    /// Chroma is taken from the nearest sample, plenty for thumbnails.
+    fn to_rgb(&self) -> Vec<u8> {
+        let (offset, scale, chroma_scale) = self.ranges();
+        let middle = (1u32 << (self.depth - 1)) as f32;
+        let weights = match self.matrix {
+            DAV1D_MC_IDENTITY => None,
+            DAV1D_MC_BT709 => Some((0.2126, 0.0722)),
+            DAV1D_MC_FCC => Some((0.30, 0.11)),
+            DAV1D_MC_SMPTE240 => Some((0.212, 0.087)),
+            DAV1D_MC_BT2020_NCL | DAV1D_MC_BT2020_CL => Some((0.2627, 0.0593)),
+            // BT.601, also what encoders mean when they don't say
+            _ => Some((0.299, 0.114)),
+        };
+        let mut rgb = Vec::with_capacity(self.width * self.height * 3);
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let luma = (self.sample(0, x, y) - offset) / scale;
+                if self.grayscale {
+                    rgb.extend([to_8bit(luma); 3]);
+                    continue;
+                }
+                let (cx, cy) = (x >> self.shift.0, y >> self.shift.1);
+                let (u, v) = (self.sample(1, cx, cy), self.sample(2, cx, cy));
+                let [r, g, b] = match weights {
+                    // The planes hold G, B and R
+                    None => [(v - offset) / scale, luma, (u - offset) / scale],
+                    Some((kr, kb)) => {
+                        let (cb, cr) = ((u - middle) / chroma_scale, (v - middle) / chroma_scale);
+                        let r = luma + 2.0 * (1.0 - kr) * cr;
+                        let b = luma + 2.0 * (1.0 - kb) * cb;
+                        [r, (luma - kr * r - kb * b) / (1.0 - kr - kb), b]
+                    }
+                };
+                rgb.extend([to_8bit(r), to_8bit(g), to_8bit(b)]);
+            }
+        }
+        rgb
+    }
+}
+
+fn to_8bit(value: f32) -> u8 {
+    (value.clamp(0.0, 1.0) * 255.0).round() as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{codecs::avif::AvifEncoder, ImageEncoder, Rgb, Rgba};
+
+    fn encode(image: &DynamicImage) -> Vec<u8> {
+        let mut avif = Vec::new();
+        let encoder = AvifEncoder::new_with_speed_quality(&mut avif, 10, 100);
+        encoder
+            .write_image(image.as_bytes(), image.width(), image.height(), image.color().into())
+            .unwrap();
+        avif
+    }
+
+    fn close(a: &[u8], b: &[u8]) -> bool {
+        a.iter().zip(b).all(|(&a, &b)| a.abs_diff(b) <= 12)
+    }
+
+    #[test]
+    fn colors_come_back() {
+        let image = RgbImage::from_fn(64, 48, |x, _| match x / 16 {
+            0 => Rgb([220, 30, 30]),
+            1 => Rgb([30, 200, 40]),
+            2 => Rgb([20, 40, 210]),
+            _ => Rgb([128, 128, 128]),
+        });
+        let decoded = decode(&encode(&DynamicImage::ImageRgb8(image.clone()))).unwrap();
+        assert_eq!((decoded.width(), decoded.height()), (64, 48));
+        let decoded = decoded.to_rgb8();
+        for (x, y) in [(8, 10), (24, 20), (40, 30), (56, 40)] {
+            let (want, got) = (image.get_pixel(x, y), decoded.get_pixel(x, y));
+            assert!(close(&want.0, &got.0), "{want:?} came back as {got:?}");
+        }
+    }
+
+    #[test]
+    fn alpha_comes_back() {
+        let image = RgbaImage::from_fn(32, 32, |x, _| Rgba([200, 100, 50, if x < 16 { 255 } else { 0 }]));
+        let decoded = decode(&encode(&DynamicImage::ImageRgba8(image))).unwrap();
+        let DynamicImage::ImageRgba8(decoded) = decoded else {
+            panic!("no alpha channel");
+        };
+        assert!(close(&decoded.get_pixel(4, 4).0, &[200, 100, 50, 255]));
+        assert_eq!(decoded.get_pixel(28, 4).0[3], 0);
+    }
+
+    #[test]
+    fn broken_files_are_errors() {
+        let avif = encode(&DynamicImage::ImageRgb8(RgbImage::new(16, 16)));
+        assert!(decode(&avif[..avif.len() / 2]).is_err());
+        assert!(decode(b"not an avif").is_err());
+    }
+}
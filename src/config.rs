@@ -0,0 +1,298 @@
+use clap::Parser;
+use crate::{strip::StripPolicy, tags::RulesSpec};
+use serde::Deserialize;
+use std::{
+    fmt,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    path::{Path, PathBuf},
+};
+
+const DEFAULT_CONFIG_FILE: &str = "rusty-gallery.toml";
+
+/// First path segments of the routes every gallery serves under its prefix
+/// (see `gallery_routes` in main.rs); another gallery can't be mounted there.
+const GALLERY_ROUTES: [&str; 7] = ["random", "album", "thumbs", "render", "wallpapers", "health", "api"];
+
+/// Command line flags. Each one can also come from an environment variable;
+/// anything left unset falls back to the config file, then to the defaults.
+#[derive(Parser)]
+#[command(version, about = "Wallpaper gallery web app")]
+struct Cli {
+    /// Config file [default: rusty-gallery.toml, if present]
+    #[arg(short, long, env = "RUSTY_GALLERY_CONFIG")]
+    config: Option<PathBuf>,
+
+    /// Address to listen on, e.g. 0.0.0.0 to expose the gallery to the LAN
+    #[arg(short, long, env = "RUSTY_GALLERY_BIND")]
+    bind: Option<IpAddr>,
+
+    /// Port to listen on
+    #[arg(short, long, env = "RUSTY_GALLERY_PORT")]
+    port: Option<u16>,
+
+    /// Folder with the wallpapers (when the config file defines no galleries)
+    #[arg(short, long, env = "RUSTY_GALLERY_DIR")]
+    dir: Option<PathBuf>,
+
+    /// Tag rules file (TOML or JSON) for galleries without their own rules
+    #[arg(short, long, env = "RUSTY_GALLERY_TAGS")]
+    tags: Option<PathBuf>,
+
+    /// Folder for generated thumbnails
+    #[arg(long, env = "RUSTY_GALLERY_CACHE_DIR")]
+    cache_dir: Option<PathBuf>,
+}
+
+/// Settings read from the config file. Relative paths in it are resolved
+/// against the folder the file lives in.
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct FileConfig {
+    bind: Option<IpAddr>,
+    port: Option<u16>,
+    dir: Option<PathBuf>,
+    tags: Option<PathBuf>,
+    cache_dir: Option<PathBuf>,
+    thumb_sizes: Option<Vec<u32>>,
+    max_render_side: Option<u32>,
+    render_cache_mb: Option<u64>,
+    negotiate_formats: Option<bool>,
+    strip_metadata: Option<StripPolicy>,
+    ffmpeg: Option<PathBuf>,
+    rescan_secs: Option<u64>,
+    #[serde(rename = "gallery")]
+    galleries: Vec<FileGallery>,
+}
+
+/// One `[[gallery]]` table of the config file.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FileGallery {
+    name: String,
+    prefix: Option<String>,
+    title: Option<String>,
+    random_title: Option<String>,
+    dir: PathBuf,
+    tags: Option<PathBuf>,
+    tag_rules: Option<RulesSpec>,
+    #[serde(default)]
+    quotes: Vec<String>,
+}
+
+/// Final settings after merging flags, environment, config file and defaults.
+pub struct Config {
+    pub addr: SocketAddr,
+    pub galleries: Vec<GalleryConfig>,
+    pub cache_dir: PathBuf,
+    /// Thumbnail widths that `/thumbs/{size}/...` accepts
+    pub thumb_sizes: Vec<u32>,
+    /// Largest width or height `/render` will produce
+    pub max_render_side: u32,
+    /// Most megabytes of rendered images kept on disk
+    pub render_cache_mb: u64,
+    /// Transcode thumbnails and renders to WebP/AVIF when the browser takes them
+    pub negotiate_formats: bool,
+    /// Metadata removed from wallpapers as they are served
+    pub strip_metadata: StripPolicy,
+    /// ffmpeg program for the poster frames of videos, a name looked up in
+    /// `PATH` or a path
+    pub ffmpeg: PathBuf,
+    /// Seconds between full rescans of the image folders, 0 for never
+    pub rescan_secs: u64,
+}
+
+/// A gallery mounted under its own URL prefix.
+pub struct GalleryConfig {
+    pub name: String,
+    /// `""` for the root, otherwise `/name` style without a trailing slash
+    pub prefix: String,
+    pub title: String,
+    pub random_title: String,
+    pub image_dir: PathBuf,
+    pub tag_rules: RulesSource,
+    pub quotes: Vec<String>,
+}
+
+pub enum RulesSource {
+    File(PathBuf),
+    Inline(RulesSpec),
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(PathBuf, std::io::Error),
+    Parse(PathBuf, String),
+    Gallery(String, String),
+    Invalid(String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io(path, e) => write!(f, "cannot read {}: {e}", path.display()),
+            ConfigError::Parse(path, e) => write!(f, "invalid config {}: {e}", path.display()),
+            ConfigError::Gallery(name, e) => write!(f, "gallery {name:?}: {e}"),
+            ConfigError::Invalid(e) => write!(f, "invalid config: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl Config {
+    /// Builds the configuration with precedence flags > environment > config
+    /// file > defaults.
+    ///
+    /// `[[gallery]]` tables in the config file each mount a gallery; without
+    /// any, a single gallery is served at the root from `dir` and `tags`.
+    pub fn load() -> Result<Self, ConfigError> {
+        let cli = Cli::parse();
+
+        // A config file named explicitly must exist; the default one is optional
+        let (file, base) = match &cli.config {
+            Some(path) => (read_file(path)?, parent_dir(path)),
+            None => {
+                let path = Path::new(DEFAULT_CONFIG_FILE);
+                if path.is_file() {
+                    (read_file(path)?, PathBuf::new())
+                } else {
+                    (FileConfig::default(), PathBuf::new())
+                }
+            }
+        };
+
+        let bind = cli.bind.or(file.bind).unwrap_or(IpAddr::V4(Ipv4Addr::LOCALHOST));
+        let port = cli.port.or(file.port).unwrap_or(3000);
+        let tag_rules = cli
+            .tags
+            .or_else(|| file.tags.map(|t| base.join(t)))
+            .unwrap_or_else(|| PathBuf::from("tags.toml"));
+
+        let cache_dir = cli
+            .cache_dir
+            .or_else(|| file.cache_dir.map(|d| base.join(d)))
+            .unwrap_or_else(|| PathBuf::from("cache"));
+        let thumb_sizes = file.thumb_sizes.unwrap_or_else(|| vec![240, 480, 960]);
+        if thumb_sizes.is_empty() || thumb_sizes.iter().any(|&s| !(16..=4096).contains(&s)) {
+            return Err(ConfigError::Invalid("thumb_sizes must be between 16 and 4096".into()));
+        }
+        // A bare name is looked up in PATH, anything else is a path like the others
+        let ffmpeg = file
+            .ffmpeg
+            .map(|p| if p.components().count() > 1 { base.join(p) } else { p })
+            .unwrap_or_else(|| PathBuf::from("ffmpeg"));
+        let max_render_side = file.max_render_side.unwrap_or(7680);
+        if !(16..=16384).contains(&max_render_side) {
+            return Err(ConfigError::Invalid("max_render_side must be between 16 and 16384".into()));
+        }
+        let render_cache_mb = file.render_cache_mb.unwrap_or(2048);
+        if render_cache_mb == 0 {
+            return Err(ConfigError::Invalid("render_cache_mb must be at least 1".into()));
+        }
+
+        let galleries = if file.galleries.is_empty() {
+            let image_dir = cli
+                .dir
+                .or_else(|| file.dir.map(|d| base.join(d)))
+                .unwrap_or_else(|| PathBuf::from("static/wallpapers"));
+            vec![GalleryConfig {
+                name: "wallpapers".to_string(),
+                prefix: String::new(),
+                title: "Wallpaper Gallery".to_string(),
+                random_title: "Random Wallpaper".to_string(),
+                image_dir,
+                tag_rules: RulesSource::File(tag_rules),
+                quotes: default_quotes(),
+            }]
+        } else {
+            let mut galleries = Vec::with_capacity(file.galleries.len());
+            for g in file.galleries {
+                let gallery = resolve_gallery(g, &base, &tag_rules)?;
+                if let Some(other) = galleries.iter().find(|o: &&GalleryConfig| o.prefix == gallery.prefix) {
+                    return Err(ConfigError::Gallery(
+                        gallery.name,
+                        format!("prefix {:?} is already used by {:?}", gallery.prefix, other.name),
+                    ));
+                }
+                if let Some(clash) = galleries.iter().find_map(|o| route_clash(o, &gallery)) {
+                    return Err(ConfigError::Gallery(gallery.name, clash));
+                }
+                galleries.push(gallery);
+            }
+            galleries
+        };
+
+        Ok(Config {
+            addr: SocketAddr::new(bind, port),
+            galleries,
+            cache_dir,
+            thumb_sizes,
+            max_render_side,
+            render_cache_mb,
+            negotiate_formats: file.negotiate_formats.unwrap_or(false),
+            strip_metadata: file.strip_metadata.unwrap_or_default(),
+            ffmpeg,
+            rescan_secs: file.rescan_secs.unwrap_or(300),
+        })
+    }
+}
+
+fn resolve_gallery(g: FileGallery, base: &Path, default_rules: &Path) -> Result<GalleryConfig, ConfigError> {
+    let invalid = |reason: &str| ConfigError::Gallery(g.name.clone(), reason.to_string());
+
+    let prefix = match g.prefix.as_deref() {
+        None => format!("/{}", g.name),
+        Some(p) => p.trim_end_matches('/').to_string(),
+    };
+    if !(prefix.is_empty() || prefix.starts_with('/')) {
+        return Err(invalid("prefix must start with '/'"));
+    }
+    if prefix.split('/').skip(1).any(|seg| seg.is_empty() || seg == "." || seg == "..") {
+        return Err(invalid("prefix must be a plain path like /name"));
+    }
+
+    let tag_rules = match (g.tags, g.tag_rules) {
+        (Some(_), Some(_)) => return Err(invalid("set either tags or tag_rules, not both")),
+        (Some(path), None) => RulesSource::File(base.join(path)),
+        (None, Some(spec)) => RulesSource::Inline(spec),
+        (None, None) => RulesSource::File(default_rules.to_path_buf()),
+    };
+
+    let title = g.title.unwrap_or_else(|| format!("{} Gallery", g.name));
+    Ok(GalleryConfig {
+        random_title: g.random_title.unwrap_or_else(|| format!("Random {}", g.name)),
+        quotes: if g.quotes.is_empty() { default_quotes() } else { g.quotes },
+        image_dir: base.join(g.dir),
+        name: g.name,
+        prefix,
+        title,
+        tag_rules,
+    })
+}
+
+/// Why gallery `b` can't be mounted next to `a`, if one's prefix lands in
+/// the other's routes, like `/a/wallpapers` next to `/a`.
+fn route_clash(a: &GalleryConfig, b: &GalleryConfig) -> Option<String> {
+    let route_under = |outer: &str, inner: &str| {
+        let first = inner.strip_prefix(outer)?.strip_prefix('/')?.split('/').next()?;
+        GALLERY_ROUTES.into_iter().find(|&route| route == first)
+    };
+    if let Some(route) = route_under(&a.prefix, &b.prefix) {
+        return Some(format!("prefix {:?} is taken by the /{route} route of {:?}", b.prefix, a.name));
+    }
+    let route = route_under(&b.prefix, &a.prefix)?;
+    Some(format!("its /{route} route would cover {:?} at {:?}", a.name, a.prefix))
+}
+
+fn default_quotes() -> Vec<String> {
+    vec!["Love is the Law, Love under Will.".to_string()]
+}
+
+fn read_file(path: &Path) -> Result<FileConfig, ConfigError> {
+    let text = std::fs::read_to_string(path).map_err(|e| ConfigError::Io(path.to_path_buf(), e))?;
+    toml::from_str(&text).map_err(|e| ConfigError::Parse(path.to_path_buf(), e.to_string()))
+}
+
+fn parent_dir(path: &Path) -> PathBuf {
+    path.parent().map(Path::to_path_buf).unwrap_or_default()
+}
@@ -0,0 +1,475 @@
+use exif::{In, Tag, Value};
+use regex::Regex;
+use std::{
+    fs::File,
+    io::{self, BufReader, Read, Seek, SeekFrom},
+    path::Path,
+    sync::LazyLock,
+};
+
+/// Metadata segments bigger than this are skipped rather than read
+const MAX_SEGMENT: u32 = 4 << 20;
+
+/// Metadata embedded in an image by cameras and editors, merged from its
+/// EXIF, XMP and IPTC blocks (in that order of preference).
+#[derive(Clone, Default)]
+pub struct Embedded {
+    pub camera: Option<String>,
+    /// When the picture was taken or made, `YYYY-MM-DD hh:mm:ss` or a prefix
+    pub taken: Option<String>,
+    pub artist: Option<String>,
+    pub copyright: Option<String>,
+    pub description: Option<String>,
+    /// XMP subjects and IPTC keywords
+    pub keywords: Vec<String>,
+}
+
+/// Raw metadata blocks found in a file.
+#[derive(Default)]
+struct Blocks {
+    /// TIFF structure, without any `Exif\0\0` header
+    exif: Option<Vec<u8>>,
+    xmp: Option<String>,
+    /// IPTC-IIM records
+    iptc: Option<Vec<u8>>,
+}
+
+/// Reads the metadata of a JPEG, PNG or WebP file. Only the metadata blocks
+/// are read, the pixel data is skipped. `None` if there is nothing useful.
+pub fn read_embedded(path: &Path) -> Option<Embedded> {
+    let mut file = BufReader::new(File::open(path).ok()?);
+    let mut magic = [0; 12];
+    file.read_exact(&mut magic).ok()?;
+    file.rewind().ok()?;
+    let blocks = if magic.starts_with(&[0xFF, 0xD8]) {
+        jpeg_blocks(&mut file)
+    } else if magic.starts_with(b"\x89PNG\r\n\x1a\n") {
+        png_blocks(&mut file)
+    } else if magic.starts_with(b"RIFF") && &magic[8..12] == b"WEBP" {
+        webp_blocks(&mut file)
+    } else {
+        return None;
+    };
+    // A truncated file still gives whatever came before the damage
+    let blocks = blocks.unwrap_or_else(|partial| partial);
+
+    let mut meta = Embedded::default();
+    if let Some(raw) = blocks.exif {
+        read_exif(raw, &mut meta);
+    }
+    if let Some(xmp) = &blocks.xmp {
+        read_xmp(xmp, &mut meta);
+    }
+    if let Some(iptc) = &blocks.iptc {
+        read_iptc(iptc, &mut meta);
+    }
+    let empty = meta.camera.is_none()
+        && meta.taken.is_none()
+        && meta.artist.is_none()
+        && meta.copyright.is_none()
+        && meta.description.is_none()
+        && meta.keywords.is_empty();
+    (!empty).then_some(meta)
+}
+
+type BlockResult = Result<Blocks, Blocks>;
+
+/// Walks the JPEG markers up to the image data.
+fn jpeg_blocks(file: &mut BufReader<File>) -> BlockResult {
+    let mut blocks = Blocks::default();
+    let mut read = || -> io::Result<()> {
+        file.seek(SeekFrom::Start(2))?;
+        loop {
+            let mut marker = [0; 2];
+            file.read_exact(&mut marker)?;
+            if marker[0] != 0xFF {
+                return Ok(());
+            }
+            // Start of scan: no metadata after this point
+            if marker[1] == 0xDA || marker[1] == 0xD9 {
+                return Ok(());
+            }
+            let mut len = [0; 2];
+            file.read_exact(&mut len)?;
+            let len = u32::from(u16::from_be_bytes(len)).saturating_sub(2);
+            if !matches!(marker[1], 0xE1 | 0xED) {
+                file.seek_relative(i64::from(len))?;
+                continue;
+            }
+            let data = read_block(file, len)?;
+            if let Some(tiff) = data.strip_prefix(b"Exif\0\0") {
+                blocks.exif.get_or_insert_with(|| tiff.to_vec());
+            } else if let Some(xmp) = data.strip_prefix(b"http://ns.adobe.com/xap/1.0/\0") {
+                blocks.xmp.get_or_insert_with(|| String::from_utf8_lossy(xmp).into_owned());
+            } else if let Some(resources) = data.strip_prefix(b"Photoshop 3.0\0") {
+                if let Some(iptc) = photoshop_iptc(resources) {
+                    blocks.iptc.get_or_insert(iptc);
+                }
+            }
+        }
+    };
+    match read() {
+        Ok(()) => Ok(blocks),
+        Err(_) => Err(blocks),
+    }
+}
+
+/// Walks the PNG chunks, skipping over the image data.
+fn png_blocks(file: &mut BufReader<File>) -> BlockResult {
+    let mut blocks = Blocks::default();
+    let mut read = || -> io::Result<()> {
+        file.seek(SeekFrom::Start(8))?;
+        loop {
+            let mut header = [0; 8];
+            file.read_exact(&mut header)?;
+            let len = u32::from_be_bytes(header[..4].try_into().unwrap());
+            let kind = &header[4..];
+            match kind {
+                b"IEND" => return Ok(()),
+                b"eXIf" => blocks.exif = Some(read_block(file, len)?),
+                b"iTXt" => {
+                    if let Some(Some(xmp)) = itxt_xmp(&read_block(file, len)?) {
+                        blocks.xmp = Some(String::from_utf8_lossy(xmp).into_owned());
+                    }
+                }
+                _ => file.seek_relative(i64::from(len))?,
+            }
+            // CRC
+            file.seek_relative(4)?;
+        }
+    };
+    match read() {
+        Ok(()) => Ok(blocks),
+        Err(_) => Err(blocks),
+    }
+}
+
+/// The XMP packet in the body of a PNG `iTXt` chunk. `None` if the chunk
+/// holds something else, `Some(None)` if the packet is compressed or the
+/// chunk is broken.
+pub fn itxt_xmp(body: &[u8]) -> Option<Option<&[u8]>> {
+    let rest = body.strip_prefix(b"XML:com.adobe.xmp\0")?;
+    // Compression flag and method, then the language and translated keyword,
+    // each ended by a NUL
+    Some(match rest {
+        [0, _, rest @ ..] => rest.splitn(3, |&b| b == 0).nth(2),
+        _ => None,
+    })
+}
+
+/// Walks the RIFF chunks of a WebP file.
+fn webp_blocks(file: &mut BufReader<File>) -> BlockResult {
+    let mut blocks = Blocks::default();
+    let mut read = || -> io::Result<()> {
+        file.seek(SeekFrom::Start(12))?;
+        loop {
+            let mut header = [0; 8];
+            file.read_exact(&mut header)?;
+            let len = u32::from_le_bytes(header[4..].try_into().unwrap());
+            match &header[..4] {
+                b"EXIF" => {
+                    let data = read_block(file, len)?;
+                    let tiff = data.strip_prefix(b"Exif\0\0").unwrap_or(&data);
+                    blocks.exif = Some(tiff.to_vec());
+                }
+                b"XMP " => blocks.xmp = Some(String::from_utf8_lossy(&read_block(file, len)?).into_owned()),
+                _ => file.seek_relative(i64::from(len))?,
+            }
+            // Chunks are padded to an even size
+            if len % 2 == 1 {
+                file.seek_relative(1)?;
+            }
+        }
+    };
+    match read() {
+        Ok(()) => Ok(blocks),
+        Err(_) => Err(blocks),
+    }
+}
+
+fn read_block(file: &mut BufReader<File>, len: u32) -> io::Result<Vec<u8>> {
+    if len > MAX_SEGMENT {
+        file.seek_relative(i64::from(len))?;
+        return Ok(Vec::new());
+    }
+    let mut data = vec![0; len as usize];
+    file.read_exact(&mut data)?;
+    Ok(data)
+}
+
+/// The IPTC resource (0x0404) of a Photoshop image resource block.
+fn photoshop_iptc(mut data: &[u8]) -> Option<Vec<u8>> {
+    while data.len() >= 12 && data.starts_with(b"8BIM") {
+        let id = u16::from_be_bytes([data[4], data[5]]);
+        // Pascal string name, padded to an even length
+        let name_len = usize::from(data[6]) + 1;
+        let rest = data.get(6 + name_len + name_len % 2..)?;
+        let size = u32::from_be_bytes(rest.get(..4)?.try_into().ok()?) as usize;
+        let body = rest.get(4..4 + size)?;
+        if id == 0x0404 {
+            return Some(body.to_vec());
+        }
+        data = rest.get(4 + size + size % 2..)?;
+    }
+    None
+}
+
+fn read_exif(raw: Vec<u8>, meta: &mut Embedded) {
+    let Ok(exif) = exif::Reader::new().read_raw(raw) else { return };
+    let text = |tag: Tag| -> Option<String> {
+        let field = exif.get_field(tag, In::PRIMARY)?;
+        let Value::Ascii(values) = &field.value else { return None };
+        let joined = values
+            .iter()
+            .map(|v| String::from_utf8_lossy(v).trim().to_string())
+            .filter(|v| !v.is_empty())
+            .collect::<Vec<_>>()
+            .join(" ");
+        (!joined.is_empty()).then_some(joined)
+    };
+
+    // Models usually repeat the make ("Canon" + "Canon EOS R5")
+    meta.camera = match (text(Tag::Make), text(Tag::Model)) {
+        (Some(make), Some(model)) if model.starts_with(&make) => Some(model),
+        (Some(make), Some(model)) => Some(format!("{make} {model}")),
+        (make, model) => model.or(make),
+    };
+    meta.taken = text(Tag::DateTimeOriginal)
+        .or_else(|| text(Tag::DateTime))
+        .map(|d| d.replacen(':', "-", 2));
+    meta.artist = text(Tag::Artist);
+    meta.copyright = text(Tag::Copyright);
+    meta.description = text(Tag::ImageDescription);
+}
+
+/// Picks the few properties we show out of an XMP packet. Good enough for
+/// what cameras and editors write, without a full RDF parser.
+fn read_xmp(xmp: &str, meta: &mut Embedded) {
+    static LIST: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"(?s)<rdf:li[^>]*>(.*?)</rdf:li>").unwrap());
+
+    // dc:subject, dc:creator, ... hold a list of values
+    let list = |prop: &str| -> Vec<String> {
+        let Some(start) = xmp.find(&format!("<{prop}>")) else { return Vec::new() };
+        let Some(end) = xmp[start..].find(&format!("</{prop}>")) else { return Vec::new() };
+        LIST.captures_iter(&xmp[start..start + end])
+            .map(|c| xml_unescape(c[1].trim()))
+            .filter(|v| !v.is_empty())
+            .collect()
+    };
+    // Simple properties are either attributes or elements
+    let simple = |prop: &str| -> Option<String> {
+        let attr = format!("{prop}=\"");
+        if let Some(start) = xmp.find(&attr).map(|i| i + attr.len()) {
+            let end = xmp[start..].find('"')?;
+            return Some(xml_unescape(&xmp[start..start + end]));
+        }
+        let open = format!("<{prop}>");
+        let start = xmp.find(&open)? + open.len();
+        let end = xmp[start..].find('<')?;
+        Some(xml_unescape(xmp[start..start + end].trim())).filter(|v| !v.is_empty())
+    };
+
+    meta.keywords.extend(list("dc:subject"));
+    meta.artist = meta.artist.take().or_else(|| list("dc:creator").into_iter().next());
+    meta.copyright = meta.copyright.take().or_else(|| list("dc:rights").into_iter().next());
+    meta.description = meta.description.take().or_else(|| list("dc:description").into_iter().next());
+    meta.taken = meta.taken.take().or_else(|| {
+        let date = simple("exif:DateTimeOriginal")
+            .or_else(|| simple("photoshop:DateCreated"))
+            .or_else(|| simple("xmp:CreateDate"))?;
+        Some(date.chars().take(19).collect::<String>().replace('T', " "))
+    });
+    if meta.camera.is_none() {
+        meta.camera = match (simple("tiff:Make"), simple("tiff:Model")) {
+            (Some(make), Some(model)) if model.starts_with(&make) => Some(model),
+            (Some(make), Some(model)) => Some(format!("{make} {model}")),
+            (make, model) => model.or(make),
+        };
+    }
+}
+
+/// Reads the application record (2:xx) of IPTC-IIM data.
+fn read_iptc(mut data: &[u8], meta: &mut Embedded) {
+    let mut keywords = Vec::new();
+    while data.len() >= 5 && data[0] == 0x1C {
+        let (record, dataset) = (data[1], data[2]);
+        let len = usize::from(u16::from_be_bytes([data[3], data[4]]));
+        // Extended lengths (high bit set) only show up for huge values
+        let Some(value) = data.get(5..5 + len).filter(|_| len & 0x8000 == 0) else { break };
+        let value = String::from_utf8_lossy(value).trim().to_string();
+        data = &data[5 + len..];
+        if record != 2 || value.is_empty() {
+            continue;
+        }
+        match dataset {
+            25 => keywords.push(value),
+            80 => meta.artist = meta.artist.take().or(Some(value)),
+            116 => meta.copyright = meta.copyright.take().or(Some(value)),
+            120 => meta.description = meta.description.take().or(Some(value)),
+            55 if value.len() == 8 && value.is_ascii() => {
+                let date = format!("{}-{}-{}", &value[..4], &value[4..6], &value[6..]);
+                meta.taken = meta.taken.take().or(Some(date));
+            }
+            _ => {}
+        }
+    }
+    for keyword in keywords {
+        if !meta.keywords.contains(&keyword) {
+            meta.keywords.push(keyword);
+        }
+    }
+}
+
+fn xml_unescape(s: &str) -> String {
+    s.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{codecs::png::PngEncoder, ImageEncoder};
+
+    const XMP_PACKET: &str = r#"<x:xmpmeta xmlns:x="adobe:ns:meta/"><rdf:RDF><rdf:Description
+ exif:DateTimeOriginal="2023-05-06T07:08:09+02:00">
+ <tiff:Make>Canon</tiff:Make><tiff:Model>Canon EOS R5</tiff:Model>
+ <dc:subject><rdf:Bag><rdf:li>sunset</rdf:li><rdf:li>sea</rdf:li></rdf:Bag></dc:subject>
+ <dc:creator><rdf:Seq><rdf:li>Ann &amp; Bo</rdf:li></rdf:Seq></dc:creator>
+ <dc:rights><rdf:Alt><rdf:li xml:lang="x-default">CC BY 4.0</rdf:li></rdf:Alt></dc:rights>
+</rdf:Description></rdf:RDF></x:xmpmeta>"#;
+
+    fn iptc_record(dataset: u8, value: &str) -> Vec<u8> {
+        let len = u16::try_from(value.len()).unwrap().to_be_bytes();
+        [&[0x1C, 2, dataset, len[0], len[1]], value.as_bytes()].concat()
+    }
+
+    /// A Photoshop image resource with an empty name.
+    fn photoshop_resource(id: u16, body: &[u8]) -> Vec<u8> {
+        let size = (body.len() as u32).to_be_bytes();
+        let mut resource = [b"8BIM".as_slice(), &id.to_be_bytes(), &[0, 0], &size, body].concat();
+        if body.len() % 2 == 1 {
+            resource.push(0);
+        }
+        resource
+    }
+
+    fn temp_file(name: &str, data: &[u8]) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!("rusty-gallery-{}-{name}", std::process::id()));
+        std::fs::write(&path, data).unwrap();
+        path
+    }
+
+    #[test]
+    fn iptc_fields() {
+        let data = [
+            iptc_record(25, "sunset"),
+            iptc_record(25, "sea"),
+            iptc_record(80, "Ann"),
+            iptc_record(116, "(c) Ann"),
+            iptc_record(120, "Evening at the beach"),
+            iptc_record(55, "20240131"),
+            // Envelope record, not the application one
+            [&[0x1C, 1, 80, 0, 3], b"Bob".as_slice()].concat(),
+        ]
+        .concat();
+        let mut meta = Embedded { keywords: vec!["sea".to_string()], ..Embedded::default() };
+        read_iptc(&data, &mut meta);
+        assert_eq!(meta.keywords, ["sea", "sunset"]);
+        assert_eq!(meta.artist.as_deref(), Some("Ann"));
+        assert_eq!(meta.copyright.as_deref(), Some("(c) Ann"));
+        assert_eq!(meta.description.as_deref(), Some("Evening at the beach"));
+        assert_eq!(meta.taken.as_deref(), Some("2024-01-31"));
+    }
+
+    #[test]
+    fn iptc_stops_at_a_record_past_the_end() {
+        let data = [iptc_record(80, "Ann"), vec![0x1C, 2, 116, 0, 50, b'x']].concat();
+        let mut meta = Embedded::default();
+        read_iptc(&data, &mut meta);
+        assert_eq!(meta.artist.as_deref(), Some("Ann"));
+        assert_eq!(meta.copyright, None);
+    }
+
+    #[test]
+    fn photoshop_iptc_resource() {
+        let iptc = iptc_record(80, "Ann");
+        // A resource with an odd size and a name comes first
+        let name = [2, b'a', b'b', 0];
+        let mut other = [b"8BIM".as_slice(), &[0x03, 0xED], &name, &3u32.to_be_bytes(), b"abc", &[0]].concat();
+        other.extend(photoshop_resource(0x0404, &iptc));
+        assert_eq!(photoshop_iptc(&other), Some(iptc));
+        assert_eq!(photoshop_iptc(&photoshop_resource(0x03ED, b"xy")), None);
+        assert_eq!(photoshop_iptc(b"not a resource block"), None);
+    }
+
+    #[test]
+    fn xmp_properties() {
+        let mut meta = Embedded::default();
+        read_xmp(XMP_PACKET, &mut meta);
+        assert_eq!(meta.keywords, ["sunset", "sea"]);
+        assert_eq!(meta.artist.as_deref(), Some("Ann & Bo"));
+        assert_eq!(meta.copyright.as_deref(), Some("CC BY 4.0"));
+        assert_eq!(meta.taken.as_deref(), Some("2023-05-06 07:08:09"));
+        assert_eq!(meta.camera.as_deref(), Some("Canon EOS R5"));
+    }
+
+    #[test]
+    fn exif_wins_over_xmp() {
+        let mut meta = Embedded { artist: Some("From EXIF".to_string()), ..Embedded::default() };
+        read_xmp(XMP_PACKET, &mut meta);
+        assert_eq!(meta.artist.as_deref(), Some("From EXIF"));
+    }
+
+    #[test]
+    fn png_with_xmp_in_an_itxt_chunk() {
+        let mut png = Vec::new();
+        PngEncoder::new(&mut png)
+            .write_image(&[0; 4 * 4 * 3], 4, 4, image::ExtendedColorType::Rgb8)
+            .unwrap();
+        // Keyword, compression flag and method, empty language and translated keyword
+        let body = [b"XML:com.adobe.xmp\0".as_slice(), &[0, 0, 0, 0], XMP_PACKET.as_bytes()].concat();
+        assert_eq!(itxt_xmp(&body), Some(Some(XMP_PACKET.as_bytes())));
+
+        let mut crc = crc32fast::Hasher::new();
+        crc.update(b"iTXt");
+        crc.update(&body);
+        let len = (body.len() as u32).to_be_bytes();
+        let chunk = [&len, b"iTXt".as_slice(), &body, &crc.finalize().to_be_bytes()].concat();
+        // Right after the signature and IHDR
+        let data = [&png[..33], &chunk, &png[33..]].concat();
+        let path = temp_file("itxt.png", &data);
+        let meta = read_embedded(&path);
+        std::fs::remove_file(&path).unwrap();
+        let meta = meta.unwrap();
+        assert_eq!(meta.artist.as_deref(), Some("Ann & Bo"));
+        assert_eq!(meta.keywords, ["sunset", "sea"]);
+    }
+
+    #[test]
+    fn compressed_or_other_itxt_chunks() {
+        assert_eq!(itxt_xmp(b"XML:com.adobe.xmp\0\x01\x00\0\0compressed"), Some(None));
+        assert_eq!(itxt_xmp(b"Comment\0\0\0\0\0hello"), None);
+    }
+
+    #[test]
+    fn jpeg_blocks_are_merged() {
+        let segment = |marker: u8, payload: &[u8]| {
+            let len = u16::try_from(payload.len() + 2).unwrap().to_be_bytes();
+            [&[0xFF, marker, len[0], len[1]], payload].concat()
+        };
+        let iptc = [iptc_record(80, "Ann"), iptc_record(25, "beach")].concat();
+        let photoshop = [b"Photoshop 3.0\0".as_slice(), &photoshop_resource(0x0404, &iptc)].concat();
+        let xmp = [b"http://ns.adobe.com/xap/1.0/\0".as_slice(), XMP_PACKET.as_bytes()].concat();
+        let data = [&[0xFF, 0xD8], &segment(0xED, &photoshop)[..], &segment(0xE1, &xmp), &[0xFF, 0xDA]].concat();
+        let path = temp_file("blocks.jpg", &data);
+        let meta = read_embedded(&path);
+        std::fs::remove_file(&path).unwrap();
+        let meta = meta.unwrap();
+        // XMP comes before IPTC
+        assert_eq!(meta.artist.as_deref(), Some("Ann & Bo"));
+        assert_eq!(meta.keywords, ["sunset", "sea", "beach"]);
+    }
+}
@@ -0,0 +1,519 @@
+use crate::{
+    embedded::{read_embedded, Embedded},
+    meta::{image_of_sidecar, sidecar_path, Sidecar},
+    sniff::{self, Format, Problem},
+    tags::{size_tags, TagRules},
+    thumbs,
+};
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use std::{
+    collections::{BTreeMap, HashSet},
+    ffi::OsStr,
+    fs::Metadata,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, RwLock},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use image::imageops::FilterType;
+use tokio::sync::{mpsc, Notify};
+
+/// How long to wait for more file events before applying a batch
+const DEBOUNCE: Duration = Duration::from_millis(300);
+/// Longest side of the blurry previews, in pixels
+const PREVIEW_SIZE: u32 = 16;
+/// Images decoded per batch when making placeholders
+const PLACEHOLDER_BATCH: usize = 16;
+
+/// One wallpaper as known to the index.
+#[derive(Clone)]
+pub struct ImageEntry {
+    /// Path relative to the gallery folder, with `/` separators
+    pub name: String,
+    /// Tags from the rules, then the size tags
+    pub tags: Vec<String>,
+    /// File size in bytes
+    pub size: u64,
+    /// Last modification, in seconds since the Unix epoch
+    pub mtime: Option<u64>,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    /// Real format of the file, whatever its extension says
+    pub format: Format,
+    /// A GIF, APNG or WebP with more than one frame
+    pub animated: bool,
+    /// Filled in the background after the image is indexed
+    pub placeholder: Option<Placeholder>,
+    /// From the image's sidecar file, if any
+    pub sidecar: Option<Sidecar>,
+    /// EXIF, XMP and IPTC data from the file itself
+    pub embedded: Option<Embedded>,
+    /// Modification time of the sidecar, to notice edits to it
+    sidecar_mtime: Option<SystemTime>,
+}
+
+/// A file named like an image that can't be shown, kept out of the gallery
+/// until it changes.
+pub struct Quarantined {
+    pub size: u64,
+    pub mtime: Option<u64>,
+    pub problem: Problem,
+}
+
+/// What reading a file gave.
+enum Indexed {
+    Image(Arc<ImageEntry>),
+    Quarantined(Arc<Quarantined>),
+    /// Not there, or not named like an image
+    Gone,
+}
+
+/// Stand-in painted while an image loads. Both parts are missing if the image
+/// could not be decoded.
+#[derive(Clone, Default)]
+pub struct Placeholder {
+    /// Most common colour, as `#rrggbb`
+    pub color: Option<String>,
+    /// Tiny WebP version as a `data:` URI, blurry once stretched
+    pub preview: Option<String>,
+}
+
+/// All images of a gallery folder and its subfolders, built once at startup
+/// and then kept up to date from file system events, with a periodic full
+/// rescan as a fallback for events that get lost (network shares,
+/// overflowing inotify queues). Placeholders need the whole image decoded, so
+/// they are made afterwards in the background instead of holding up startup.
+pub struct ImageIndex {
+    dir: PathBuf,
+    rules: Arc<TagRules>,
+    /// For the poster frames of videos
+    ffmpeg: Arc<Path>,
+    entries: RwLock<Arc<BTreeMap<String, Arc<ImageEntry>>>>,
+    /// Files that failed the content check, by name
+    quarantine: RwLock<Arc<BTreeMap<String, Arc<Quarantined>>>>,
+    watcher: Mutex<Option<RecommendedWatcher>>,
+    /// Woken when there may be images without a placeholder
+    placeholders: Notify,
+}
+
+/// What the watcher tells the update task.
+enum Change {
+    Paths(Vec<PathBuf>),
+    Rescan,
+}
+
+impl ImageIndex {
+    /// Scans `dir` and starts watching it. A `rescan` of zero turns the
+    /// periodic rescan off.
+    pub async fn open(dir: PathBuf, rules: Arc<TagRules>, rescan: Duration, ffmpeg: Arc<Path>) -> Arc<Self> {
+        let index = Arc::new(ImageIndex {
+            dir,
+            rules,
+            ffmpeg,
+            entries: RwLock::new(Arc::default()),
+            quarantine: RwLock::new(Arc::default()),
+            watcher: Mutex::new(None),
+            placeholders: Notify::new(),
+        });
+        index.rescan().await;
+
+        let (tx, rx) = mpsc::unbounded_channel();
+        let events = tx.clone();
+        let watcher = notify::recommended_watcher(move |res: notify::Result<notify::Event>| {
+            let change = match res {
+                Ok(event) if event.need_rescan() => Change::Rescan,
+                Ok(event) if event.kind.is_access() => return,
+                Ok(event) => Change::Paths(event.paths),
+                Err(_) => Change::Rescan,
+            };
+            let _ = events.send(change);
+        })
+        .and_then(|mut w| w.watch(&index.dir, RecursiveMode::Recursive).map(|_| w));
+        match watcher {
+            Ok(w) => *index.watcher.lock().unwrap() = Some(w),
+            Err(e) => println!(
+                "Cannot watch {}, new files show up on the next rescan: {e}",
+                index.dir.display()
+            ),
+        }
+
+        tokio::spawn(index.clone().apply_changes(rx));
+        tokio::spawn(index.clone().fill_placeholders());
+        if !rescan.is_zero() {
+            tokio::spawn(async move {
+                let mut ticks = tokio::time::interval(rescan);
+                ticks.tick().await;
+                loop {
+                    ticks.tick().await;
+                    if tx.send(Change::Rescan).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+
+        index
+    }
+
+    /// Every indexed image, sorted by name.
+    pub fn snapshot(&self) -> Arc<BTreeMap<String, Arc<ImageEntry>>> {
+        self.entries.read().unwrap().clone()
+    }
+
+    /// Files left out of the gallery because their content is broken.
+    pub fn quarantined(&self) -> Arc<BTreeMap<String, Arc<Quarantined>>> {
+        self.quarantine.read().unwrap().clone()
+    }
+
+    pub fn get(&self, name: &str) -> Option<Arc<ImageEntry>> {
+        self.entries.read().unwrap().get(name).cloned()
+    }
+
+    pub fn path_of(&self, entry: &ImageEntry) -> PathBuf {
+        self.dir.join(&entry.name)
+    }
+
+    /// Applies batches of watcher events and rescan requests, one at a time.
+    async fn apply_changes(self: Arc<Self>, mut rx: mpsc::UnboundedReceiver<Change>) {
+        while let Some(first) = rx.recv().await {
+            // Let a burst of events (a big copy, an editor's save dance) settle
+            tokio::time::sleep(DEBOUNCE).await;
+            let mut rescan = false;
+            let mut paths = HashSet::new();
+            for change in std::iter::once(first).chain(std::iter::from_fn(|| rx.try_recv().ok())) {
+                match change {
+                    Change::Rescan => rescan = true,
+                    Change::Paths(p) => paths.extend(p),
+                }
+            }
+
+            // Moving or deleting a whole folder only produces an event for the
+            // folder itself, not for the images inside it
+            let mut names = Vec::new();
+            let current = self.snapshot();
+            let quarantined = self.quarantined();
+            for path in &paths {
+                let Some(name) = self.name_of(path) else { continue };
+                if path.is_dir() {
+                    rescan = true;
+                } else if has_image_extension(path) {
+                    names.push(name);
+                } else if let Some(image) = image_of_sidecar(path).and_then(|p| self.name_of(&p)) {
+                    names.push(image);
+                } else if !path.exists() {
+                    let folder = format!("{name}/");
+                    let inside = current.keys().chain(quarantined.keys()).filter(|n| n.starts_with(&folder));
+                    names.extend(inside.cloned());
+                }
+            }
+
+            if rescan {
+                self.rescan().await;
+            } else {
+                self.refresh(names).await;
+            }
+        }
+    }
+
+    /// Index name of a path inside the folder, or `None` for paths inside a
+    /// hidden folder, which the rescan skips too.
+    fn name_of(&self, path: &Path) -> Option<String> {
+        let rel = path.strip_prefix(&self.dir).ok()?;
+        let parts: Vec<&str> = rel.components().map(|c| c.as_os_str().to_str()).collect::<Option<_>>()?;
+        let (_, folders) = parts.split_last()?;
+        if folders.iter().any(|f| f.starts_with('.')) {
+            return None;
+        }
+        Some(parts.join("/"))
+    }
+
+    /// Re-reads the whole folder, reusing entries whose file is unchanged.
+    async fn rescan(&self) {
+        let dir = self.dir.clone();
+        let names = tokio::task::spawn_blocking(move || list_images(&dir)).await;
+        let names = match names {
+            Ok(Ok(names)) => names,
+            // Keep what is known until the folder can be read again; a share
+            // that drops out for a moment shouldn't empty the gallery
+            Ok(Err(e)) => {
+                println!("Cannot read {}: {e}", self.dir.display());
+                return;
+            }
+            Err(_) => return,
+        };
+
+        let found: HashSet<&String> = names.iter().collect();
+        let (current, quarantined) = (self.snapshot(), self.quarantined());
+        let known = current.keys().chain(quarantined.keys());
+        let gone: Vec<String> = known.filter(|n| !found.contains(n)).cloned().collect();
+        self.refresh(names.into_iter().chain(gone).collect()).await;
+    }
+
+    /// Re-reads the given file names: new and modified images are (re)indexed,
+    /// broken ones quarantined and vanished ones dropped.
+    async fn refresh(&self, names: Vec<String>) {
+        if names.is_empty() {
+            return;
+        }
+        let dir = self.dir.clone();
+        let rules = self.rules.clone();
+        let (current, quarantined) = (self.snapshot(), self.quarantined());
+        let updates = tokio::task::spawn_blocking(move || {
+            names
+                .into_iter()
+                .map(|name| {
+                    let read = || read_entry(&dir, &name, &rules, current.get(&name), quarantined.get(&name));
+                    // A bug in one of the format parsers costs that file, not the whole batch
+                    let entry = sniff::catch_crash(read).unwrap_or_else(|problem| crashed(&dir.join(&name), problem));
+                    (name, entry)
+                })
+                .collect::<Vec<_>>()
+        })
+        .await
+        .unwrap_or_default();
+
+        // Both maps are locked together so no file is ever in both or neither
+        let mut entries = self.entries.write().unwrap();
+        let mut quarantine = self.quarantine.write().unwrap();
+        let (map, bad) = (Arc::make_mut(&mut entries), Arc::make_mut(&mut quarantine));
+        for (name, entry) in updates {
+            match entry {
+                Indexed::Image(entry) => {
+                    bad.remove(&name);
+                    map.insert(name, entry);
+                }
+                Indexed::Quarantined(q) => {
+                    map.remove(&name);
+                    bad.insert(name, q);
+                }
+                Indexed::Gone => {
+                    map.remove(&name);
+                    bad.remove(&name);
+                }
+            }
+        }
+        self.placeholders.notify_one();
+    }
+
+    /// Makes placeholders for images that have none yet, a batch at a time,
+    /// whenever the index changes.
+    async fn fill_placeholders(self: Arc<Self>) {
+        loop {
+            self.placeholders.notified().await;
+            loop {
+                let todo: Vec<Arc<ImageEntry>> = self
+                    .snapshot()
+                    .values()
+                    .filter(|e| e.placeholder.is_none())
+                    .take(PLACEHOLDER_BATCH)
+                    .cloned()
+                    .collect();
+                if todo.is_empty() {
+                    break;
+                }
+                let dir = self.dir.clone();
+                let ffmpeg = self.ffmpeg.clone();
+                let made = tokio::task::spawn_blocking(move || {
+                    todo.into_iter()
+                        .map(|entry| {
+                            let placeholder = make_placeholder(&dir.join(&entry.name), &ffmpeg);
+                            (entry, placeholder)
+                        })
+                        .collect::<Vec<_>>()
+                })
+                .await
+                .unwrap_or_default();
+
+                // Skip entries that changed while their image was being decoded
+                let mut entries = self.entries.write().unwrap();
+                let map = Arc::make_mut(&mut entries);
+                for (old, placeholder) in made {
+                    if let Some(current) = map.get_mut(&old.name).filter(|c| Arc::ptr_eq(c, &old)) {
+                        *current = Arc::new(ImageEntry {
+                            placeholder: Some(placeholder),
+                            ..(*old).clone()
+                        });
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Indexes `dir/name`, checking that its content really is an image.
+/// `previous` (or `quarantined`) is kept as is when the file's size and mtime
+/// did not change.
+fn read_entry(
+    dir: &Path,
+    name: &str,
+    rules: &TagRules,
+    previous: Option<&Arc<ImageEntry>>,
+    quarantined: Option<&Arc<Quarantined>>,
+) -> Indexed {
+    let path = dir.join(name);
+    if !has_image_extension(&path) {
+        return Indexed::Gone;
+    }
+    let Some(meta) = std::fs::metadata(&path).ok().filter(|m| m.is_file()) else {
+        return Indexed::Gone;
+    };
+    let size = meta.len();
+    let mtime = mtime_secs(&meta);
+
+    let sidecar_file = sidecar_path(&path);
+    let sidecar_mtime = sidecar_file
+        .as_ref()
+        .and_then(|p| std::fs::metadata(p).ok())
+        .and_then(|m| m.modified().ok());
+
+    if let Some(q) = quarantined.filter(|q| q.size == size && q.mtime == mtime) {
+        return Indexed::Quarantined(q.clone());
+    }
+    let unchanged = previous.filter(|p| p.size == size && p.mtime == mtime);
+    if let Some(prev) = unchanged.filter(|p| p.sidecar_mtime == sidecar_mtime) {
+        return Indexed::Image(prev.clone());
+    }
+
+    let (format, animated, (width, height)) = match unchanged {
+        Some(prev) => (prev.format, prev.animated, (prev.width, prev.height)),
+        None => match sniff::check(&path) {
+            Ok(checked) => (checked.format, checked.animated, checked.size.unzip()),
+            Err(problem) => {
+                println!("Ignoring {}: {problem}", path.display());
+                return Indexed::Quarantined(Arc::new(Quarantined { size, mtime, problem }));
+            }
+        },
+    };
+
+    let sidecar = sidecar_file.and_then(|p| match Sidecar::load(&p) {
+        Ok(sidecar) => Some(sidecar),
+        Err(e) => {
+            println!("Ignoring {}: {e}", p.display());
+            None
+        }
+    });
+
+    let embedded = read_embedded(&path);
+
+    // Tags named in the sidecar or embedded as keywords count like rule tags
+    let mut tags: Vec<String> = rules.tags_for(name).into_iter().map(String::from).collect();
+    let sidecar_tags = sidecar.iter().flat_map(|s| &s.tags);
+    let keywords = embedded.iter().flat_map(|e| &e.keywords);
+    let mut extra = sidecar_tags.chain(keywords).peekable();
+    if extra.peek().is_some() {
+        tags.retain(|t| t != rules.fallback());
+    }
+    for tag in extra {
+        if !tags.contains(tag) {
+            tags.push(tag.clone());
+        }
+    }
+    if let (Some(w), Some(h)) = (width, height) {
+        for tag in size_tags(w, h) {
+            if !tags.iter().any(|t| t == tag) {
+                tags.push(tag.to_string());
+            }
+        }
+    }
+    Indexed::Image(Arc::new(ImageEntry {
+        name: name.to_string(),
+        tags,
+        size,
+        mtime,
+        width,
+        height,
+        format,
+        animated,
+        // Only the sidecar changed, the picture is the same
+        placeholder: unchanged.and_then(|p| p.placeholder.clone()),
+        sidecar,
+        sidecar_mtime,
+        embedded,
+    }))
+}
+
+/// Quarantines a file whose check panicked, until it changes.
+fn crashed(path: &Path, problem: Problem) -> Indexed {
+    let Ok(meta) = std::fs::metadata(path) else { return Indexed::Gone };
+    println!("Ignoring {}: {problem}", path.display());
+    Indexed::Quarantined(Arc::new(Quarantined { size: meta.len(), mtime: mtime_secs(&meta), problem }))
+}
+
+fn mtime_secs(meta: &Metadata) -> Option<u64> {
+    meta.modified()
+        .ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map(|d| d.as_secs())
+}
+
+/// Dominant colour and tiny preview of an image, or of a video's poster frame.
+fn make_placeholder(path: &Path, ffmpeg: &Path) -> Placeholder {
+    let Ok(img) = thumbs::decode(path, ffmpeg) else {
+        return Placeholder::default();
+    };
+    let small = img.resize(PREVIEW_SIZE, PREVIEW_SIZE, FilterType::Triangle).to_rgb8();
+
+    // Most common colour after dropping the low bits, averaged over its bucket
+    let mut buckets: BTreeMap<[u8; 3], ([u32; 3], u32)> = BTreeMap::new();
+    for px in small.pixels() {
+        let (sum, count) = buckets.entry(px.0.map(|c| c >> 4)).or_default();
+        for (s, c) in sum.iter_mut().zip(px.0) {
+            *s += u32::from(c);
+        }
+        *count += 1;
+    }
+    let color = buckets.values().max_by_key(|(_, count)| *count).map(|(sum, count)| {
+        let [r, g, b] = sum.map(|s| s / count);
+        format!("#{r:02x}{g:02x}{b:02x}")
+    });
+
+    let webp = webp::Encoder::from_rgb(&small, small.width(), small.height()).encode(50.0);
+    Placeholder {
+        color,
+        preview: Some(format!("data:image/webp;base64,{}", BASE64.encode(&*webp))),
+    }
+}
+
+/// Relative paths of every image under `dir`. Hidden folders are skipped and
+/// symlinked folders are not followed.
+fn list_images(dir: &Path) -> std::io::Result<Vec<String>> {
+    let mut images = Vec::new();
+    let mut pending = vec![(dir.to_path_buf(), String::new())];
+    while let Some((folder, prefix)) = pending.pop() {
+        let entries = match std::fs::read_dir(&folder) {
+            Ok(entries) => entries,
+            // Only the top folder is required to exist
+            Err(e) if prefix.is_empty() => return Err(e),
+            Err(_) => continue,
+        };
+        // An entry that vanishes or can't be read is skipped; the next scan
+        // picks it up again if it's still there
+        for entry in entries.flatten() {
+            let Some(file_name) = entry.file_name().to_str().map(String::from) else {
+                continue;
+            };
+            let name = format!("{prefix}{file_name}");
+            let path = entry.path();
+            let Ok(file_type) = entry.file_type() else {
+                continue;
+            };
+            if file_type.is_dir() {
+                if !file_name.starts_with('.') {
+                    pending.push((path, format!("{name}/")));
+                }
+            } else if path.is_file() && has_image_extension(&path) {
+                images.push(name);
+            }
+        }
+    }
+    Ok(images)
+}
+
+/// Whether `path` is named like an image. Only the name is looked at; the
+/// content is checked when the file is indexed.
+fn has_image_extension(path: &Path) -> bool {
+    path.extension().and_then(OsStr::to_str).is_some_and(|ext| {
+        let ext = ext.to_ascii_lowercase();
+        Format::ALL.iter().any(|f| f.extensions().contains(&ext.as_str()))
+    })
+}
@@ -1,216 +1,943 @@
-use axum::{response::Html, routing::get, Router};
+mod api;
+mod avif;
+mod config;
+mod embedded;
+mod index;
+mod meta;
+mod sniff;
+mod sort;
+mod strip;
+mod svg;
+mod tags;
+mod thumbs;
+
+use axum::{
+    extract::{Path as UrlPath, RawQuery, Request, State},
+    http::{header, HeaderValue, StatusCode},
+    response::{Html, IntoResponse, Redirect, Response},
+    routing::get,
+    Router,
+};
+use axum_extra::extract::Query;
+use config::{Config, RulesSource};
+use index::{ImageEntry, ImageIndex};
 use rand::seq::SliceRandom;
-use std::{ffi::OsStr, net::SocketAddr};
+use serde::{Deserialize, Deserializer};
+use std::{collections::BTreeMap, fmt, path::{Path, PathBuf}, str::FromStr, sync::Arc, time::Duration};
+use sniff::Format;
+use sort::SortMode;
+use strip::Stripper;
+use tags::{MatchMode, TagRules, TagRulesError, SIZE_TAGS};
+use thumbs::{OutputFormat, RenderMode, RenderSpec, ThumbCache, ThumbError};
 use tokio::net::TcpListener;
-use tower_http::{services::ServeDir, trace::TraceLayer};
+use tower::ServiceExt;
+use tower_http::{
+    services::ServeFile,
+    trace::TraceLayer,
+};
+
+/// Smallest thumbnail width used for grid cards
+const CARD_WIDTH: u32 = 360;
+
+/// `sizes` of grid card images, matching the grid's columns
+const CARD_SIZES: &str = "(max-width: 400px) 100vw, (max-width: 800px) 50vw, 360px";
+
+/// Gallery cards per page unless `?per_page=` says otherwise
+const PER_PAGE: usize = 60;
+const MAX_PER_PAGE: usize = 500;
 
-const IMAGE_DIR: &str = "static/wallpapers";
+/// One gallery mounted under its URL prefix; the state of its handlers.
+struct Gallery {
+    /// `""` when mounted at the root
+    prefix: String,
+    title: String,
+    random_title: String,
+    image_dir: PathBuf,
+    rules: Arc<TagRules>,
+    index: Arc<ImageIndex>,
+    quotes: Vec<String>,
+    thumbs: Arc<ThumbCache>,
+    strip: Arc<Stripper>,
+    /// Largest width or height `/render` produces
+    max_render_side: u32,
+    /// Whether generated images follow the browser's `Accept` header
+    negotiate_formats: bool,
+    /// Whether there is a gallery index at `/` to link back to
+    show_index: bool,
+}
 
 #[tokio::main]
 async fn main() {
-    // Static images under /wallpapers
-    let static_service = ServeDir::new(IMAGE_DIR);
+    let config = Config::load().unwrap_or_else(|e| {
+        eprintln!("{e}");
+        std::process::exit(1);
+    });
 
-    let app = Router::new()
-        .route("/", get(gallery))
-        .route("/random", get(random_wallpaper))
-        .nest_service("/wallpapers", static_service)
-        .layer(TraceLayer::new_for_http());
+    let ffmpeg: Arc<Path> = config.ffmpeg.into();
+    // Only videos need it, so a missing ffmpeg is worth a warning rather than an exit
+    if let Err(e) = std::process::Command::new(&*ffmpeg).arg("-version").output() {
+        println!("Cannot run {}, videos will have no thumbnails: {e}", ffmpeg.display());
+    }
+    let thumbs = Arc::new(ThumbCache::new(
+        &config.cache_dir,
+        config.thumb_sizes,
+        ffmpeg.clone(),
+        config.render_cache_mb << 20,
+    ));
+    let strip = Arc::new(Stripper::new(&config.cache_dir, config.strip_metadata));
+    let rescan = Duration::from_secs(config.rescan_secs);
+    let has_root = config.galleries.iter().any(|g| g.prefix.is_empty());
+    let mut galleries = Vec::with_capacity(config.galleries.len());
+    for g in config.galleries {
+        let rules = match g.tag_rules {
+            RulesSource::File(path) => match TagRules::load(&path) {
+                Ok(rules) => rules,
+                Err(TagRulesError::Io(e)) if e.kind() == std::io::ErrorKind::NotFound => {
+                    println!(
+                        "No {} found, every image in {} will use the fallback tag",
+                        path.display(),
+                        g.name
+                    );
+                    TagRules::empty()
+                }
+                Err(e) => {
+                    eprintln!("{}: {e}", path.display());
+                    std::process::exit(1);
+                }
+            },
+            RulesSource::Inline(spec) => TagRules::compile(spec).unwrap_or_else(|e| {
+                eprintln!("gallery {:?}: {e}", g.name);
+                std::process::exit(1);
+            }),
+        };
+
+        let rules = Arc::new(rules);
+        let index = ImageIndex::open(g.image_dir.clone(), rules.clone(), rescan, ffmpeg.clone()).await;
+        galleries.push(Arc::new(Gallery {
+            prefix: g.prefix,
+            title: g.title,
+            random_title: g.random_title,
+            image_dir: g.image_dir,
+            rules,
+            index,
+            quotes: g.quotes,
+            thumbs: thumbs.clone(),
+            strip: strip.clone(),
+            max_render_side: config.max_render_side,
+            negotiate_formats: config.negotiate_formats,
+            show_index: !has_root,
+        }));
+    }
+
+    let mut app = Router::new();
+    for gallery in &galleries {
+        let routes = gallery_routes(gallery.clone());
+        app = if gallery.prefix.is_empty() {
+            app.merge(routes)
+        } else {
+            // Nested routers answer on `/name` only, so send `/name/` there too
+            let home = gallery.prefix.clone();
+            let redirect = move |RawQuery(query): RawQuery| async move {
+                match query {
+                    Some(q) => Redirect::permanent(&format!("{home}?{q}")),
+                    None => Redirect::permanent(&home),
+                }
+            };
+            app.route(&format!("{}/", gallery.prefix), get(redirect))
+                .nest(&gallery.prefix, routes)
+        };
+    }
+    if !has_root {
+        app = app.route("/", get(gallery_index).with_state(galleries.clone()));
+    }
+    let app = app.layer(TraceLayer::new_for_http());
 
-    let addr = SocketAddr::from(([127, 0, 0, 1], 3000));
-    println!("Wallpapers Gallery running at http://{}/", addr);
+    let addr = config.addr;
+    for gallery in &galleries {
+        println!("{} running at http://{}{}/", gallery.title, addr, gallery.prefix);
+    }
 
     let listener = TcpListener::bind(addr).await.unwrap();
     axum::serve(listener, app).await.unwrap();
 }
 
-async fn gallery() -> Html<String> {
-    let images = match list_images().await {
-        Ok(v) if !v.is_empty() => v,
-        _ => {
-            return Html(minimal_page(
-                "Wallpapers Gallery",
-                r#"<p>No images found. Add files to <code>static/wallpapers</code>.</p>"#,
-            ))
+// New top level routes need adding to `GALLERY_ROUTES` in config.rs
+fn gallery_routes(gallery: Arc<Gallery>) -> Router {
+    Router::new()
+        .route("/", get(gallery_page))
+        .route("/random", get(random_wallpaper))
+        .route("/random/image", get(random_image))
+        .route("/album/*path", get(album_page))
+        .route("/thumbs/:size/*name", get(thumbnail))
+        .route("/render/*name", get(render))
+        .route("/wallpapers/*name", get(wallpaper))
+        .route("/health", get(health_page))
+        .route("/api/images", get(api::images))
+        .route("/api/tags", get(api::tags))
+        .route("/api/random", get(api::random))
+        .route("/api/*rest", get(api::not_found))
+        .with_state(gallery)
+}
+
+/// Landing page listing every gallery, used when none is mounted at the root.
+async fn gallery_index(State(galleries): State<Vec<Arc<Gallery>>>) -> Html<String> {
+    let mut cards = String::new();
+    for gallery in &galleries {
+        let images = gallery.index.snapshot();
+        let prefix = html_escape(&gallery.prefix);
+        let cover = match images.keys().next() {
+            Some(name) => format!(
+                r#"<img src="{prefix}/thumbs/{}/{}" alt="Cover">"#,
+                gallery.thumbs.size_for(CARD_WIDTH),
+                urlencode_path(name)
+            ),
+            None => r#"<div class="empty-cover"></div>"#.to_string(),
+        };
+        cards.push_str(&format!(
+            r#"<a class="gallery-card" href="{prefix}/">
+                   {cover}
+                   <span class="gallery-name">{title} <span class="count">{count}</span></span>
+               </a>"#,
+            title = html_escape(&gallery.title),
+            count = images.len(),
+        ));
+    }
+
+    let body = format!(
+        r#"
+        <header>
+            <h1>Galleries</h1>
+        </header>
+
+        <section class="galleries">{cards}</section>
+        "#
+    );
+
+    Html(styled_page("Galleries", &body))
+}
+
+/// Tags selected through `?tag=...&tag=...&match=any|all`.
+#[derive(Deserialize)]
+struct TagFilter {
+    #[serde(default)]
+    tag: Vec<String>,
+    #[serde(default, rename = "match")]
+    mode: MatchMode,
+}
+
+impl TagFilter {
+    /// The filter as a query string, `""` when nothing is selected.
+    fn to_query(&self) -> String {
+        let mut parts: Vec<String> = self.tag.iter().map(|t| format!("tag={}", urlencode(t))).collect();
+        if self.mode != MatchMode::Any {
+            parts.push(format!("match={}", self.mode.as_str()));
         }
-    };
+        if parts.is_empty() {
+            String::new()
+        } else {
+            format!("?{}", parts.join("&"))
+        }
+    }
+}
 
-    // Derive simple tags by filename keywords; adjust to your naming scheme
+/// How far an image's aspect ratio may be off the screen's with `?fit=`
+const FIT_TOLERANCE: f64 = 0.1;
 
-    let mut grid = String::new();
-    for img in &images {
-        let src = format!("/wallpapers/{}", html_escape(img));
-        let tag = if img.to_ascii_lowercase().contains("bocchi") {
-           
-            "Bocchi The Rock"
+/// `?fit=3440x1440`: only images at least as big as that screen, with about
+/// the same aspect ratio (within `?tolerance=`, a fraction).
+#[derive(Deserialize)]
+struct ScreenFit {
+    #[serde(default, deserialize_with = "optional_resolution")]
+    fit: Option<Resolution>,
+    tolerance: Option<f64>,
+}
 
+#[derive(Clone, Copy)]
+struct Resolution {
+    width: u32,
+    height: u32,
+}
+
+impl ScreenFit {
+    fn matches(&self, img: &ImageEntry) -> bool {
+        let Some(screen) = self.fit else { return true };
+        let (Some(width), Some(height)) = (img.width, img.height) else {
+            return false;
+        };
+        let tolerance = self.tolerance.unwrap_or(FIT_TOLERANCE).clamp(0.0, 1.0);
+        let ratio = (f64::from(width) / f64::from(height)) / (f64::from(screen.width) / f64::from(screen.height));
+        width >= screen.width && height >= screen.height && (ratio - 1.0).abs() <= tolerance
+    }
+
+    /// Appends the fit to a query string made by [`TagFilter::to_query`].
+    fn add_to_query(&self, mut query: String) -> String {
+        let Some(screen) = self.fit else { return query };
+        query.push(if query.is_empty() { '?' } else { '&' });
+        query.push_str(&format!("fit={screen}"));
+        if let Some(tolerance) = self.tolerance {
+            query.push_str(&format!("&tolerance={tolerance}"));
+        }
+        query
+    }
+}
+
+impl fmt::Display for Resolution {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}x{}", self.width, self.height)
+    }
+}
+
+impl FromStr for Resolution {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid resolution {s:?}, expected something like 1920x1080");
+        let (width, height) = s.split_once(['x', 'X']).ok_or_else(invalid)?;
+        let width: u32 = width.trim().parse().map_err(|_| invalid())?;
+        let height: u32 = height.trim().parse().map_err(|_| invalid())?;
+        if width == 0 || height == 0 {
+            return Err(invalid());
+        }
+        Ok(Resolution { width, height })
+    }
+}
 
-        } else if img.to_ascii_lowercase().contains("rentagirlfriend") {
+/// An empty `?fit=` (as sent by a cleared form field) means no fit.
+fn optional_resolution<'de, D: Deserializer<'de>>(d: D) -> Result<Option<Resolution>, D::Error> {
+    let s = String::deserialize(d)?;
+    if s.is_empty() {
+        return Ok(None);
+    }
+    s.parse().map(Some).map_err(serde::de::Error::custom)
+}
 
-            "Rent-a-Girlfriend"
+/// `?sort=` and, for random order, `?seed=`.
+#[derive(Deserialize)]
+struct Sorting {
+    #[serde(default)]
+    sort: SortMode,
+    seed: Option<u64>,
+}
 
-        } else if img.to_ascii_lowercase().contains("kon") {
+impl Sorting {
+    /// The seed to shuffle with, a fresh one if none was given. Fresh seeds
+    /// stay below 2^53 so JavaScript reads them back from JSON unchanged.
+    fn seed(&self) -> u64 {
+        self.seed.unwrap_or_else(|| rand::random::<u64>() >> 11)
+    }
+}
 
-            "K-ON"
+/// `?page=` and `?per_page=` of paginated listings. Pages start at 1.
+#[derive(Deserialize)]
+struct Paging {
+    page: Option<usize>,
+    per_page: Option<usize>,
+}
 
+impl Paging {
+    fn page(&self) -> usize {
+        self.page.unwrap_or(1).max(1)
+    }
 
-        } else if img.to_ascii_lowercase().contains("lovelive") {
+    fn per_page(&self) -> usize {
+        self.per_page.unwrap_or(PER_PAGE).clamp(1, MAX_PER_PAGE)
+    }
 
-            "Love Live"
-        
-        } else if img.to_ascii_lowercase().contains("madoka") {
+    /// Whether the request asked for a page at all.
+    fn is_set(&self) -> bool {
+        self.page.is_some() || self.per_page.is_some()
+    }
 
-          "Madoka Magica"
+    /// Number of pages needed for `total` items, at least one.
+    fn pages(&self, total: usize) -> usize {
+        total.div_ceil(self.per_page()).max(1)
+    }
 
-        } else if img.to_ascii_lowercase().contains("frieren") {
+    /// The items of `page`, empty past the last page.
+    fn slice<'a, T>(&self, items: &'a [T], page: usize) -> &'a [T] {
+        let start = (page - 1).saturating_mul(self.per_page()).min(items.len());
+        let end = start.saturating_add(self.per_page()).min(items.len());
+        &items[start..end]
+    }
+}
 
-           "Frieren"
+/// Image count of every tag in use, sorted by name with the fallback tag and
+/// then the size tags last.
+fn tag_counts<'a>(rules: &TagRules, images: impl IntoIterator<Item = &'a Arc<ImageEntry>>) -> Vec<(&'a str, usize)> {
+    let mut counts: BTreeMap<&str, usize> = BTreeMap::new();
+    for img in images {
+        for tag in &img.tags {
+            *counts.entry(tag).or_default() += 1;
+        }
+    }
+    let fallback = counts.remove_entry(rules.fallback());
+    let sizes: Vec<(&str, usize)> = SIZE_TAGS.iter().filter_map(|t| counts.remove_entry(t)).collect();
+    counts.into_iter().chain(fallback).chain(sizes).collect()
+}
 
+async fn gallery_page(
+    State(gallery): State<Arc<Gallery>>,
+    Query(filter): Query<TagFilter>,
+    Query(sorting): Query<Sorting>,
+    Query(paging): Query<Paging>,
+) -> Response {
+    gallery_view(&gallery, "", &filter, &sorting, &paging)
+}
 
-        } else if img.to_ascii_lowercase().contains("kobayashi") {
+/// A subfolder of the gallery, with every image below it.
+async fn album_page(
+    State(gallery): State<Arc<Gallery>>,
+    UrlPath(album): UrlPath<String>,
+    Query(filter): Query<TagFilter>,
+    Query(sorting): Query<Sorting>,
+    Query(paging): Query<Paging>,
+) -> Response {
+    gallery_view(&gallery, album.trim_matches('/'), &filter, &sorting, &paging)
+}
 
+/// Album card data for one subfolder.
+struct Album<'a> {
+    /// Path relative to the gallery folder
+    path: &'a str,
+    count: usize,
+    cover: &'a ImageEntry,
+}
 
-            "Kobayashi's Dragon Maid"
+/// Direct subfolders of `album` (`""` for the gallery root). The cover is an
+/// image called `cover.*` right inside the folder, or else its first image.
+fn sub_albums<'a>(images: &'a BTreeMap<String, Arc<ImageEntry>>, album: &str) -> Vec<Album<'a>> {
+    let scope = album_scope(album);
+    let mut albums: BTreeMap<&str, Album> = BTreeMap::new();
+    for (name, img) in images.range(scope.clone()..).take_while(|(n, _)| n.starts_with(&scope)) {
+        let rest = &name[scope.len()..];
+        let Some(slash) = rest.find('/') else { continue };
+        let path = &name[..scope.len() + slash];
+        let sub = albums.entry(path).or_insert(Album { path, count: 0, cover: img });
+        sub.count += 1;
+        let file = &rest[slash + 1..];
+        if file.starts_with("cover.") {
+            sub.cover = img;
+        }
+    }
+    albums.into_values().collect()
+}
 
-        } else if img.to_ascii_lowercase().contains("lycoris") {
+/// Name prefix shared by every image in `album`.
+fn album_scope(album: &str) -> String {
+    if album.is_empty() {
+        String::new()
+    } else {
+        format!("{album}/")
+    }
+}
 
-            "Lycoris Recoil"
+/// The grid of a gallery or album. Only the images matching the filter are
+/// rendered, one page at a time.
+fn gallery_view(gallery: &Gallery, album: &str, filter: &TagFilter, sorting: &Sorting, paging: &Paging) -> Response {
+    let all_images = gallery.index.snapshot();
+    if all_images.is_empty() {
+        return Html(no_images_page(&gallery.title, &gallery.image_dir)).into_response();
+    }
+    let scope = album_scope(album);
+    let images: Vec<&Arc<ImageEntry>> = all_images
+        .range(scope.clone()..)
+        .take_while(|(n, _)| n.starts_with(&scope))
+        .map(|(_, img)| img)
+        .collect();
+    if images.is_empty() {
+        return (StatusCode::NOT_FOUND, Html(minimal_page("Not found", "<p>No such album.</p>"))).into_response();
+    }
 
-        } // else if img.to_ascii_lowercase().contains("gits") {
+    let prefix = html_escape(&gallery.prefix);
+    let title = html_escape(&gallery.title);
+    let random_title = html_escape(&gallery.random_title);
+    let home = home_link(gallery);
+    let thumb_size = gallery.thumbs.size_for(CARD_WIDTH);
 
-           // "Ghost in the Shell"  
+    // Breadcrumbs down to the current album
+    let mut breadcrumbs = String::new();
+    if !album.is_empty() {
+        breadcrumbs.push_str(&format!(r#"<nav class="breadcrumbs"><a href="{prefix}/">{title}</a>"#));
+        let mut path = String::new();
+        let segments: Vec<&str> = album.split('/').collect();
+        for (i, segment) in segments.iter().enumerate() {
+            if !path.is_empty() {
+                path.push('/');
+            }
+            path.push_str(segment);
+            let segment = html_escape(segment);
+            if i + 1 == segments.len() {
+                breadcrumbs.push_str(&format!(r#" / <span>{segment}</span>"#));
+            } else {
+                breadcrumbs.push_str(&format!(
+                    r#" / <a href="{prefix}/album/{}">{segment}</a>"#,
+                    urlencode_path(&path)
+                ));
+            }
+        }
+        breadcrumbs.push_str("</nav>");
+    }
 
-       // }
-         else if img.to_ascii_lowercase().contains("overlord") {
-            
-            "Overlord"
+    // Cards for the subfolders
+    let mut albums = String::new();
+    for sub in sub_albums(&all_images, album) {
+        let label = sub.path.rsplit('/').next().unwrap_or(sub.path);
+        albums.push_str(&format!(
+            r#"<a class="gallery-card" href="{prefix}/album/{path}">
+                   <img src="{prefix}/thumbs/{thumb_size}/{cover}" alt="Cover" loading="lazy">
+                   <span class="gallery-name">📁 {label} <span class="count">{count}</span></span>
+               </a>"#,
+            path = urlencode_path(sub.path),
+            cover = urlencode_path(&sub.cover.name),
+            label = html_escape(label),
+            count = sub.count,
+        ));
+    }
+    if !albums.is_empty() {
+        albums = format!(r#"<section class="galleries albums">{albums}</section>"#);
+    }
 
-        } else {
-           
-            "Various"
+    // Tags come from the gallery's tag rules
+    let mut matching: Vec<&Arc<ImageEntry>> = images
+        .iter()
+        .copied()
+        .filter(|img| filter.mode.matches(&img.tags, &filter.tag))
+        .collect();
+    // A random order gets a seed in every link, so paging doesn't reshuffle
+    let seed = sorting.seed();
+    sorting.sort.sort(&mut matching, seed);
+    let pages = paging.pages(matching.len());
+    let page = paging.page().min(pages);
+    let mut grid = String::new();
+    for img in paging.slice(&matching, page) {
+        let src = format!("{prefix}/wallpapers/{}", urlencode_path(&img.name));
+        let thumb = format!("{prefix}/thumbs/{thumb_size}/{}", urlencode_path(&img.name));
+        let responsive = responsive_attrs(gallery, img, CARD_SIZES, false);
+        // Thumbnails of moving images are stills, so the lightbox only gets the original
+        let moving = img.format.is_video() || img.animated;
+        let full_srcset = if moving { String::new() } else { html_escape(&srcset(gallery, img, true)) };
+        let (motion, badge) = match (img.format.is_video(), img.animated) {
+            (true, _) => (r#" data-motion="video""#, r#"<span class="badge" title="Video">▶</span>"#),
+            (_, true) => (r#" data-motion="image""#, r#"<span class="badge" title="Animated">⟳</span>"#),
+            _ => ("", ""),
         };
+        let placeholder = placeholder_style(img);
+        let info = image_info(img);
+        let alt = img
+            .sidecar
+            .as_ref()
+            .and_then(|s| s.alt.as_deref().or(s.title.as_deref()))
+            .map_or_else(|| "Wallpaper".to_string(), html_escape);
+        let chips: String = img
+            .tags
+            .iter()
+            .map(|t| format!(r#"<span class="chip">{}</span>"#, html_escape(t)))
+            .collect();
 
         grid.push_str(&format!(
-            r#"<a class="card" href="{src}" data-tag="{tag}">
-                   <img src="{src}" alt="Wallpaper">
+            r#"<a class="card" href="{src}" data-srcset="{full_srcset}"{motion}{info}{placeholder}>
+                   <img src="{thumb}"{responsive} alt="{alt}" loading="lazy" decoding="async">
+                   {badge}
+                   <div class="chips">{chips}</div>
                </a>"#
         ));
     }
 
+    // Page links keep the filter, the order and a non-default page size
+    let mut listing = filter.to_query();
+    let mut add = |param: String| {
+        listing.push(if listing.is_empty() { '?' } else { '&' });
+        listing.push_str(&param);
+    };
+    if sorting.sort != SortMode::Name {
+        add(format!("sort={}", sorting.sort.as_str()));
+    }
+    if sorting.sort == SortMode::Random {
+        add(format!("seed={seed}"));
+    }
+    if paging.per_page.is_some() {
+        add(format!("per_page={}", paging.per_page()));
+    }
+    let page_href = |n: usize| {
+        let sep = if listing.is_empty() { '?' } else { '&' };
+        html_escape(&format!("{listing}{sep}page={n}"))
+    };
+    let canonical = html_escape(&listing);
+
+    let mut sort_options = String::new();
+    for mode in SortMode::ALL {
+        let selected = if mode == sorting.sort { " selected" } else { "" };
+        sort_options.push_str(&format!(
+            r#"<option value="{}"{selected}>Sort: {}</option>"#,
+            mode.as_str(),
+            mode.label()
+        ));
+    }
+    let prev = if page > 1 {
+        format!(r#"<a class="btn" rel="prev" href="{}">← Previous</a>"#, page_href(page - 1))
+    } else {
+        String::new()
+    };
+    let next = if page < pages {
+        format!(r#"<a class="btn" rel="next" href="{}">Next →</a>"#, page_href(page + 1))
+    } else {
+        String::new()
+    };
+    let shown = matching.len();
+
+    // One button per tag that actually has images, fallback tag last
+    let total = images.len();
+    let active = |on: bool| if on { " active" } else { "" };
+    let mut filters = String::new();
+    for (tag, count) in tag_counts(&gallery.rules, images.iter().copied()) {
+        let class = active(filter.tag.iter().any(|t| t == tag));
+        let tag = html_escape(tag);
+        filters.push_str(&format!(
+            r#"<button class="filter-btn{class}" data-filter="{tag}">{tag} <span class="count">{count}</span></button>"#
+        ));
+    }
+    let all_class = active(filter.tag.is_empty());
+    let mode = filter.mode.as_str();
+    let query = html_escape(&filter.to_query());
+
     let body = format!(
         r#"
         <header>
-            <h1>Wallpaper Gallery</h1>
+            <h1>{title}</h1>
             <nav>
-                <a class="btn" href="/">Gallery</a>
-                <a class="btn" href="/random">🎲 Random Wallpaper</a>
+                {home}
+                <a class="btn" href="{prefix}/">Gallery</a>
+                <a class="btn" href="{prefix}/random">🎲 {random_title}</a>
+                <a class="btn" id="random-filtered" href="{prefix}/random{query}" data-base="{prefix}/random">🎲 Random from this filter</a>
             </nav>
         </header>
-    
-         <div class="filters">
-            <button class="filter-btn" data-filter="all">All</button>
-            <button class="filter-btn" data-filter="Bocchi The Rock">Bocchi The Rock!!!</button>
-            <button class="filter-btn" data-filter="K-ON">K-ON</button>
-            <button class="filter-btn" data-filter="Rent-a-Girlfriend">Rent-a-Girlfriend</button>
-            <button class="filter-btn" data-filter="Love Live">Love Live</button>
-            <button class="filter-btn" data-filter="Frieren">Frieren</button>
-            <button class="filter-btn" data-filter="Kobayashi's Dragon Maid">Kobayashi's Dragon Maid</button>
-            <button class="filter-btn" data-filter="Madoka Magica">Madoka Magica</button>
-            <button class="filter-btn" data-filter="Lycoris Recoil">Lycoris Recoil</button>
-            <button class="filter-btn" data-filter="Various">Various</button>
-
-        </div> 
-
-        <section class="grid">{grid}</section>
+        {breadcrumbs}
+        {albums}
+        <div class="filters">
+            <button class="filter-btn{all_class}" data-filter="all">All <span class="count">{total}</span></button>
+            {filters}
+            <button class="mode-btn" data-mode="{mode}" title="How selected tags combine">Match: {mode}</button>
+            <select class="mode-btn sort-select" title="Order of the images">{sort_options}</select>
+        </div>
+
+        <section class="grid" data-query="{canonical}">{grid}</section>
+
+        <nav class="pager">
+            {prev}
+            <span>Page {page} of {pages} · {shown} images</span>
+            {next}
+            <button class="filter-btn scroll-btn" title="Load the next pages while scrolling">∞ Infinite scroll</button>
+        </nav>
 
         <div id="lightbox" class="lightbox">
             <span class="close">&times;</span>
-            <img class="lightbox-img" src="" alt="Wallpaper full view">
+            <img class="lightbox-img" src="" sizes="90vw" alt="Wallpaper full view">
+            <video class="lightbox-img lightbox-video" controls loop playsinline hidden></video>
+            <div class="lightbox-info" hidden></div>
         </div>
 
         <script>
         document.addEventListener('DOMContentLoaded', () => {{
             const lightbox = document.getElementById('lightbox');
-            const lightboxImg = document.querySelector('.lightbox-img');
+            const lightboxImg = document.querySelector('img.lightbox-img');
+            const lightboxVideo = document.querySelector('.lightbox-video');
             const closeBtn = document.querySelector('.close');
+            const closeLightbox = () => {{
+                lightbox.style.display = 'none';
+                lightboxVideo.pause();
+            }};
 
-            // Lightbox for gallery cards
-            document.querySelectorAll('.card img').forEach(img => {{
-                img.addEventListener('click', e => {{
-                    e.preventDefault();
-                    if (lightbox && lightboxImg) {{
-                        lightbox.style.display = 'flex';
-                        lightboxImg.src = img.src;
+            // Credits of the image below the lightbox picture. Built with
+            // textContent, the values come from files anyone may have written
+            const lightboxInfo = document.querySelector('.lightbox-info');
+            const showInfo = card => {{
+                const data = JSON.parse(card.dataset.info || '{{}}');
+                lightboxInfo.replaceChildren();
+                if (data.title) {{
+                    const title = document.createElement('strong');
+                    title.textContent = data.title;
+                    lightboxInfo.append(title);
+                }}
+                if (data.description) {{
+                    const description = document.createElement('p');
+                    description.textContent = data.description;
+                    lightboxInfo.append(description);
+                }}
+                const rows = [
+                    ['Artist', 'artist'], ['License', 'license'], ['©', 'copyright'],
+                    ['Camera', 'camera'], ['Taken', 'taken'],
+                ];
+                for (const [label, key] of rows) {{
+                    if (!data[key]) continue;
+                    const row = document.createElement('span');
+                    row.textContent = label + ': ' + data[key];
+                    lightboxInfo.append(row);
+                }}
+                if (data.source) {{
+                    const source = document.createElement(/^https?:\/\//i.test(data.source) ? 'a' : 'span');
+                    source.textContent = data.source;
+                    if (source.tagName === 'A') {{
+                        source.href = data.source;
+                        source.target = '_blank';
+                        source.rel = 'noopener noreferrer';
                     }}
-                }});
+                    lightboxInfo.append(source);
+                }}
+                lightboxInfo.hidden = lightboxInfo.childElementCount === 0;
+            }};
+
+            // Lightbox for gallery cards, showing the original rather than the
+            // thumbnail. Delegated, so cards added by infinite scroll get it too
+            const grid = document.querySelector('.grid');
+            grid.addEventListener('click', e => {{
+                const target = e.target.closest('.card img, .card video');
+                if (!target) return;
+                e.preventDefault();
+                if (lightbox && lightboxImg) {{
+                    const card = target.closest('.card');
+                    const img = card.querySelector('img');
+                    const video = card.dataset.motion === 'video';
+                    lightbox.style.display = 'flex';
+                    lightboxImg.hidden = video;
+                    lightboxVideo.hidden = !video;
+                    if (video) {{
+                        lightboxVideo.src = card.href;
+                        lightboxVideo.play().catch(() => {{}});
+                    }} else {{
+                        // srcset first, so the original is only fetched if the screen needs it
+                        lightboxImg.srcset = card.dataset.srcset;
+                        lightboxImg.src = card.href;
+                        lightboxImg.alt = img.alt;
+                    }}
+                    showInfo(card);
+                }}
+            }});
+
+            // Videos and animated images stand still in the grid, and play
+            // over their thumbnail while the pointer is on them
+            grid.addEventListener('mouseover', e => {{
+                const card = e.target.closest('.card[data-motion]');
+                if (!card || card.querySelector('.motion')) return;
+                const still = card.querySelector('img');
+                const motion = document.createElement(card.dataset.motion === 'video' ? 'video' : 'img');
+                motion.className = 'motion';
+                if (motion.tagName === 'VIDEO') {{
+                    Object.assign(motion, {{ muted: true, loop: true, autoplay: true, playsInline: true }});
+                }} else {{
+                    motion.alt = still.alt;
+                }}
+                motion.src = card.href;
+                still.after(motion);
+            }});
+            grid.addEventListener('mouseout', e => {{
+                const card = e.target.closest('.card[data-motion]');
+                if (card && !card.contains(e.relatedTarget)) card.querySelector('.motion')?.remove();
             }});
 
             if (closeBtn && lightbox) {{
-                closeBtn.addEventListener('click', () => lightbox.style.display = 'none');
+                closeBtn.addEventListener('click', closeLightbox);
             }}
             if (lightbox) {{
                 lightbox.addEventListener('click', e => {{
-                    if (e.target === lightbox) lightbox.style.display = 'none';
+                    if (e.target === lightbox) closeLightbox();
                 }});
             }}
             document.addEventListener('keydown', e => {{
-                if (e.key === 'Escape' && lightbox) lightbox.style.display = 'none';
+                if (e.key === 'Escape' && lightbox) closeLightbox();
+            }});
+
+            // Tag filters: buttons toggle tags, the mode button switches between
+            // any (OR) and all (AND). Filtering happens on the server, so a
+            // change reloads the page with the new selection, back on page 1
+            const selected = new Set();
+            document.querySelectorAll('.filter-btn.active').forEach(btn => {{
+                if (btn.dataset.filter !== 'all') selected.add(btn.dataset.filter);
             }});
+            const modeBtn = document.querySelector('.mode-btn');
+            let mode = modeBtn ? modeBtn.dataset.mode : 'any';
 
-            // Tag filters
-            document.querySelectorAll('.filter-btn').forEach(btn => {{
+            // Keep the random seed in the address bar, so reloading shows the same order
+            if (location.search !== grid.dataset.query && !new URLSearchParams(location.search).has('page')) {{
+                history.replaceState(null, '', location.pathname + grid.dataset.query);
+            }}
+            const sortSelect = document.querySelector('.sort-select');
+
+            const applyFilter = () => {{
+                const current = new URLSearchParams(location.search);
+                const params = new URLSearchParams();
+                selected.forEach(t => params.append('tag', t));
+                if (mode !== 'any') params.set('match', mode);
+                if (sortSelect.value !== 'name') params.set('sort', sortSelect.value);
+                if (sortSelect.value === 'random' && current.get('sort') === 'random' && current.has('seed')) {{
+                    params.set('seed', current.get('seed'));
+                }}
+                if (current.has('per_page')) params.set('per_page', current.get('per_page'));
+                const query = params.toString();
+                location.href = location.pathname + (query ? '?' + query : '');
+            }};
+
+            document.querySelectorAll('.filter-btn[data-filter]').forEach(btn => {{
                 btn.addEventListener('click', () => {{
                     const filter = btn.dataset.filter;
-                    document.querySelectorAll('.card').forEach(card => {{
-                        const el = card;
-                        if (filter === 'all' || el.dataset.tag === filter) {{
-                            el.style.display = 'block';
-                        }} else {{
-                            el.style.display = 'none';
-                        }}
-                    }});
+                    if (filter === 'all') {{
+                        selected.clear();
+                    }} else if (selected.has(filter)) {{
+                        selected.delete(filter);
+                    }} else {{
+                        selected.add(filter);
+                    }}
+                    applyFilter();
                 }});
             }});
+            if (modeBtn) {{
+                modeBtn.addEventListener('click', () => {{
+                    mode = mode === 'any' ? 'all' : 'any';
+                    applyFilter();
+                }});
+            }}
+            sortSelect.addEventListener('change', applyFilter);
+
+            // Infinite scroll, remembered per browser: when the pager comes into
+            // view, the next page is fetched and its cards appended to the grid
+            const pager = document.querySelector('.pager');
+            const scrollBtn = document.querySelector('.scroll-btn');
+            let infinite = localStorage.getItem('infiniteScroll') === 'on';
+            let loading = false;
+            const loadNext = async () => {{
+                const next = pager.querySelector('a[rel=next]');
+                if (!next || loading) return;
+                loading = true;
+                try {{
+                    const res = await fetch(next.href);
+                    const doc = new DOMParser().parseFromString(await res.text(), 'text/html');
+                    doc.querySelectorAll('.grid .card').forEach(card => grid.appendChild(document.adoptNode(card)));
+                    const newNext = doc.querySelector('.pager a[rel=next]');
+                    if (newNext) next.href = newNext.href;
+                    else next.remove();
+                }} finally {{
+                    loading = false;
+                }}
+                // Short pages can leave the pager in view, which fires no new event
+                if (pager.getBoundingClientRect().top < innerHeight + 600) loadNext();
+            }};
+            const observer = new IntersectionObserver(entries => {{
+                if (infinite && entries.some(e => e.isIntersecting)) loadNext();
+            }}, {{ rootMargin: '600px' }});
+            const showScrollMode = () => {{
+                scrollBtn.classList.toggle('active', infinite);
+                pager.classList.toggle('infinite', infinite);
+            }};
+            scrollBtn.addEventListener('click', () => {{
+                infinite = !infinite;
+                localStorage.setItem('infiniteScroll', infinite ? 'on' : 'off');
+                showScrollMode();
+                if (infinite) loadNext();
+            }});
+            showScrollMode();
+            observer.observe(pager);
         }});
         </script>
         "#
     );
 
-    Html(styled_page("Wallpapers Gallery", &body))
+    Html(styled_page(&gallery.title, &body)).into_response()
 }
 
-async fn random_wallpaper() -> Html<String> {
-    let quotes = [
-        "Love is the Law, Love under Will."
-    ];
+async fn random_wallpaper(
+    State(gallery): State<Arc<Gallery>>,
+    Query(filter): Query<TagFilter>,
+    Query(fit): Query<ScreenFit>,
+) -> Html<String> {
+    let images = gallery.index.snapshot();
+    if images.is_empty() {
+        return Html(no_images_page(&gallery.random_title, &gallery.image_dir));
+    }
+    let prefix = html_escape(&gallery.prefix);
+    let random_title = html_escape(&gallery.random_title);
+    let home = home_link(&gallery);
+    let query = html_escape(&filter.to_query());
+    let fit_query = html_escape(&fit.add_to_query(filter.to_query()));
 
-    let images = match list_images().await {
-        Ok(v) if !v.is_empty() => v,
-        _ => {
-            return Html(minimal_page(
-                "Random Wallpaper",
-                r#"<p>No images found. Add files to <code>static/wallpapers</code>.</p>"#,
-            ))
-        }
+    // Tag picker, preselected with the current filter
+    let mut picker = String::new();
+    for (tag, count) in tag_counts(&gallery.rules, images.values()) {
+        let checked = if filter.tag.iter().any(|t| t == tag) { " checked" } else { "" };
+        let tag = html_escape(tag);
+        picker.push_str(&format!(
+            r#"<label class="pick"><input type="checkbox" name="tag" value="{tag}"{checked}> {tag} <span class="count">{count}</span></label>"#
+        ));
+    }
+    let selected = |mode: MatchMode| if filter.mode == mode { " selected" } else { "" };
+    let (any, all) = (selected(MatchMode::Any), selected(MatchMode::All));
+    let (fit_value, fit_label, fit_class) = match fit.fit {
+        Some(screen) => (screen.to_string(), format!("📐 Fits {screen}"), " active"),
+        None => (String::new(), "📐 Fit my screen".to_string(), ""),
     };
+    let tolerance = fit
+        .tolerance
+        .map(|t| format!(r#"<input type="hidden" name="tolerance" value="{t}">"#))
+        .unwrap_or_default();
 
-    let choice = images.choose(&mut rand::thread_rng()).unwrap();
-    let src = format!("/wallpapers/{}", html_escape(choice));
-    let quote = quotes.choose(&mut rand::thread_rng()).unwrap();
+    let candidates: Vec<&Arc<ImageEntry>> = images
+        .values()
+        .filter(|img| filter.mode.matches(&img.tags, &filter.tag))
+        .filter(|img| fit.matches(img))
+        .collect();
+    let content = match candidates.choose(&mut rand::thread_rng()) {
+        Some(choice) => {
+            let src = format!("{prefix}/wallpapers/{}", urlencode_path(&choice.name));
+            let quote = gallery.quotes.choose(&mut rand::thread_rng()).map(|q| html_escape(q)).unwrap_or_default();
+            let hero = if choice.format.is_video() {
+                let poster = format!(
+                    "{prefix}/thumbs/{}/{}",
+                    gallery.thumbs.size_for(720),
+                    urlencode_path(&choice.name)
+                );
+                format!(r#"<video class="hero" src="{src}" poster="{poster}" controls autoplay loop muted playsinline></video>"#)
+            } else {
+                // Animated images would stand still in a thumbnail
+                let responsive = if choice.animated {
+                    String::new()
+                } else {
+                    responsive_attrs(&gallery, choice, "min(720px, 90vw)", true)
+                };
+                format!(r#"<img class="hero" src="{src}"{responsive} alt="Wallpaper">"#)
+            };
+            format!(
+                r#"{hero}
+            <p class="quote">“{quote}”</p>"#
+            )
+        }
+        None if fit.fit.is_some() => {
+            r#"<p class="quote">No images match the selected tags and fit this screen.</p>"#.to_string()
+        }
+        None => r#"<p class="quote">No images match the selected tags.</p>"#.to_string(),
+    };
 
     let body = format!(
         r#"
         <header>
-            <h1>Random Wallpaper</h1>
+            <h1>{random_title}</h1>
             <nav>
-                <a class="btn" href="/">← Back to Gallery</a>
-                <a class="btn" href="/random">🔁 Another</a>
+                {home}
+                <a class="btn" href="{prefix}/{query}">← Back to Gallery</a>
+                <a class="btn" href="{prefix}/random{fit_query}">🔁 Another</a>
             </nav>
         </header>
 
+        <form class="filters picker" method="get" action="{prefix}/random">
+            {picker}
+            <select class="mode-btn" name="match" title="How selected tags combine">
+                <option value="any"{any}>Match: any</option>
+                <option value="all"{all}>Match: all</option>
+            </select>
+            <input type="hidden" name="fit" value="{fit_value}">{tolerance}
+            <button class="filter-btn fit-btn{fit_class}" type="button" title="Only images at least as big as this screen, in the same shape">{fit_label}</button>
+            <button class="filter-btn" type="submit">🎲 Shuffle these</button>
+        </form>
+
         <section class="random">
-            <img class="hero" src="{src}" alt="Wallpaper">
-            <p class="quote">“{quote}”</p>
+            {content}
         </section>
 
         <div id="lightbox" class="lightbox">
@@ -224,8 +951,24 @@ async fn random_wallpaper() -> Html<String> {
             const lightboxImg = document.querySelector('.lightbox-img');
             const closeBtn = document.querySelector('.close');
 
-            // Lightbox for hero image
-            const hero = document.querySelector('.hero');
+            // Fit my screen: the screen size in device pixels goes into the
+            // form; clicking again goes back to any size
+            const fitBtn = document.querySelector('.fit-btn');
+            if (fitBtn) {{
+                fitBtn.addEventListener('click', () => {{
+                    const fit = fitBtn.form.elements.fit;
+                    if (fit.value) {{
+                        fit.value = '';
+                    }} else {{
+                        const ratio = window.devicePixelRatio || 1;
+                        fit.value = Math.round(screen.width * ratio) + 'x' + Math.round(screen.height * ratio);
+                    }}
+                    fitBtn.form.submit();
+                }});
+            }}
+
+            // Lightbox for hero image; videos have their own controls
+            const hero = document.querySelector('img.hero');
             if (hero) {{
                 hero.addEventListener('click', e => {{
                     e.preventDefault();
@@ -252,27 +995,327 @@ async fn random_wallpaper() -> Html<String> {
         "#
     );
 
-    Html(styled_page("Random Wallpaper", &body))
-}
-
-async fn list_images() -> Result<Vec<String>, std::io::Error> {
-    let mut images = Vec::new();
-    let mut rd = tokio::fs::read_dir(IMAGE_DIR).await?;
-    while let Some(entry) = rd.next_entry().await? {
-        let path = entry.path();
-        if path.is_file() {
-            if let Some(ext) = path.extension().and_then(OsStr::to_str) {
-                let ext = ext.to_ascii_lowercase();
-                if matches!(ext.as_str(), "jpg" | "jpeg" | "png" | "gif" | "webp") {
-                    if let Some(name) = path.file_name().and_then(|n| n.to_str()) {
-                        images.push(name.to_string());
-                    }
+    Html(styled_page(&gallery.random_title, &body))
+}
+
+/// Query of `/random/image`.
+#[derive(Deserialize)]
+struct RandomImageQuery {
+    #[serde(default)]
+    tag: Vec<String>,
+    #[serde(default, rename = "match")]
+    mode: MatchMode,
+    min_width: Option<u32>,
+}
+
+/// Streams a random wallpaper itself rather than a page, so wallpaper changers
+/// can `curl` it straight into a file. Videos are left out.
+async fn random_image(
+    State(gallery): State<Arc<Gallery>>,
+    Query(query): Query<RandomImageQuery>,
+    Query(fit): Query<ScreenFit>,
+    req: Request,
+) -> Response {
+    let images = gallery.index.snapshot();
+    let candidates: Vec<&Arc<ImageEntry>> = images
+        .values()
+        .filter(|img| !img.format.is_video())
+        .filter(|img| query.mode.matches(&img.tags, &query.tag))
+        .filter(|img| query.min_width.is_none_or(|min| img.width.is_some_and(|w| w >= min)))
+        .filter(|img| fit.matches(img))
+        .collect();
+
+    let Some(choice) = candidates.choose(&mut rand::thread_rng()) else {
+        return (StatusCode::NOT_FOUND, "No images match").into_response();
+    };
+
+    let mut res = serve_wallpaper(&gallery, choice, req).await;
+    let headers = res.headers_mut();
+    headers.insert(header::CACHE_CONTROL, HeaderValue::from_static("no-store, max-age=0"));
+    let file_name = choice.name.rsplit('/').next().unwrap_or(&choice.name);
+    let disposition = format!("inline; filename=\"{}\"", file_name.replace(['"', '\\'], "_"));
+    if let Ok(value) = HeaderValue::from_str(&disposition) {
+        headers.insert(header::CONTENT_DISPOSITION, value);
+    }
+    res
+}
+
+/// A wallpaper at full size, with the metadata the config says to strip
+/// removed.
+async fn wallpaper(State(gallery): State<Arc<Gallery>>, UrlPath(name): UrlPath<String>, req: Request) -> Response {
+    match gallery.index.get(&name) {
+        Some(image) => serve_wallpaper(&gallery, &image, req).await,
+        None => StatusCode::NOT_FOUND.into_response(),
+    }
+}
+
+/// Formats browsers can't show are sent as a converted copy, when they can
+/// be decoded.
+async fn serve_wallpaper(gallery: &Gallery, image: &ImageEntry, req: Request) -> Response {
+    let source = gallery.index.path_of(image);
+    if !image.format.browser_native() && image.format.decodable() {
+        let format = negotiated_format(gallery, &req);
+        let preview = gallery.thumbs.preview(&source, format).await;
+        return generated_response(preview, gallery.negotiate_formats, req).await;
+    }
+    match gallery.strip.get(&source, image.format).await {
+        Ok(path) => {
+            let mut res = ServeFile::new(path).oneshot(req).await.into_response();
+            // The file name may lie about the format
+            if res.status().is_success() {
+                let headers = res.headers_mut();
+                headers.insert(header::CONTENT_TYPE, HeaderValue::from_static(image.format.mime()));
+                if image.format == Format::Svg {
+                    // Backs up the sanitizing, for SVGs opened on their own
+                    headers.insert(
+                        header::CONTENT_SECURITY_POLICY,
+                        HeaderValue::from_static("default-src 'none'; style-src 'unsafe-inline'; img-src data:; sandbox"),
+                    );
                 }
             }
+            res
         }
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => StatusCode::NOT_FOUND.into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
     }
-    images.sort();
-    Ok(images)
+}
+
+/// Files the index had to leave out, and images whose extension doesn't
+/// match their content, for whoever looks after the folder.
+async fn health_page(State(gallery): State<Arc<Gallery>>) -> Html<String> {
+    let images = gallery.index.snapshot();
+    let quarantined = gallery.index.quarantined();
+    let mismatched: Vec<&Arc<ImageEntry>> = images
+        .values()
+        .filter(|img| !img.format.matches_extension(Path::new(&img.name)))
+        .collect();
+    let title = html_escape(&gallery.title);
+    let prefix = html_escape(&gallery.prefix);
+    let home = home_link(&gallery);
+
+    let mut sections = String::new();
+    if !quarantined.is_empty() {
+        let mut rows = String::new();
+        for (name, q) in quarantined.iter() {
+            rows.push_str(&format!(
+                "<tr><td>{}</td><td>{}</td><td>{}</td></tr>",
+                html_escape(name),
+                html_escape(&q.problem.to_string()),
+                format_size(q.size)
+            ));
+        }
+        sections.push_str(&format!(
+            r#"<h2>Left out</h2>
+            <p>These files are named like images but can't be shown. They come back once they are fixed or replaced.</p>
+            <table class="report"><tr><th>File</th><th>Problem</th><th>Size</th></tr>{rows}</table>"#
+        ));
+    }
+    if !mismatched.is_empty() {
+        let mut rows = String::new();
+        for img in &mismatched {
+            rows.push_str(&format!(
+                r#"<tr><td><a href="{prefix}/wallpapers/{}">{}</a></td><td>{}</td></tr>"#,
+                urlencode_path(&img.name),
+                html_escape(&img.name),
+                img.format.name()
+            ));
+        }
+        sections.push_str(&format!(
+            r#"<h2>Wrong extension</h2>
+            <p>These are shown and served as what they really are, but renaming them avoids surprises elsewhere.</p>
+            <table class="report"><tr><th>File</th><th>Real format</th></tr>{rows}</table>"#
+        ));
+    }
+    if sections.is_empty() {
+        sections.push_str("<p>Every file is fine.</p>");
+    }
+
+    let body = format!(
+        r#"
+        <header>
+            <h1>{title}: health</h1>
+            <nav>{home}<a class="btn" href="{prefix}/">← Back to Gallery</a></nav>
+        </header>
+
+        <section class="health">
+            <p>{count} images, {bad} left out, {wrong} with the wrong extension.</p>
+            {sections}
+        </section>
+        "#,
+        count = images.len(),
+        bad = quarantined.len(),
+        wrong = mismatched.len(),
+    );
+    Html(styled_page(&format!("{} health", gallery.title), &body))
+}
+
+/// Serves a cached thumbnail, generating it on first request.
+async fn thumbnail(
+    State(gallery): State<Arc<Gallery>>,
+    UrlPath((size, name)): UrlPath<(u32, String)>,
+    req: Request,
+) -> Response {
+    let Some(image) = gallery.index.get(&name) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+
+    // SVGs scale by themselves
+    if !image.format.decodable() {
+        return serve_wallpaper(&gallery, &image, req).await;
+    }
+    let format = negotiated_format(&gallery, &req);
+    let thumb = gallery.thumbs.get(&gallery.index.path_of(&image), size, format).await;
+    // Browsers may still show what can't be decoded here, like AVIF grids
+    if matches!(thumb, Err(ThumbError::Image(_))) && image.format.browser_native() {
+        return serve_wallpaper(&gallery, &image, req).await;
+    }
+    generated_response(thumb, gallery.negotiate_formats, req).await
+}
+
+/// Serves a thumbnail, render or preview, or the error that kept it from
+/// being made. `vary` marks the response as depending on `Accept`.
+async fn generated_response(generated: Result<PathBuf, ThumbError>, vary: bool, req: Request) -> Response {
+    match generated {
+        Ok(path) => {
+            let mut res = ServeFile::new(path).oneshot(req).await.into_response();
+            if vary {
+                res.headers_mut().append(header::VARY, HeaderValue::from_static("accept"));
+            }
+            res
+        }
+        Err(e @ ThumbError::Size(_)) => (StatusCode::NOT_FOUND, e.to_string()).into_response(),
+        Err(ThumbError::Io(e)) if e.kind() == std::io::ErrorKind::NotFound => StatusCode::NOT_FOUND.into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+/// ` data-info=".."` with what is known about `img` beyond its tags, for the
+/// lightbox, or nothing.
+fn image_info(img: &ImageEntry) -> String {
+    let mut info = serde_json::Map::new();
+    let sidecar = img.sidecar.clone().unwrap_or_default();
+    let embedded = img.embedded.clone().unwrap_or_default();
+    // The sidecar is written for the gallery, so it wins over the file
+    let fields = [
+        ("title", sidecar.title),
+        ("artist", sidecar.artist.or(embedded.artist)),
+        ("source", sidecar.source),
+        ("license", sidecar.license),
+        ("copyright", embedded.copyright),
+        ("description", embedded.description),
+        ("camera", embedded.camera),
+        ("taken", embedded.taken),
+    ];
+    for (key, value) in fields {
+        if let Some(value) = value {
+            info.insert(key.to_string(), value.into());
+        }
+    }
+    if info.is_empty() {
+        return String::new();
+    }
+    let json = serde_json::Value::Object(info).to_string();
+    format!(r#" data-info="{}""#, html_escape(&json))
+}
+
+/// ` style=".."` painting the placeholder of `img` behind it while it loads,
+/// or nothing if it has none yet.
+fn placeholder_style(img: &ImageEntry) -> String {
+    let Some(placeholder) = &img.placeholder else { return String::new() };
+    let mut style = String::new();
+    if let Some(color) = &placeholder.color {
+        style.push_str(&format!("background-color: {color};"));
+    }
+    if let Some(preview) = &placeholder.preview {
+        style.push_str(&format!(" background-image: url({preview});"));
+    }
+    if style.is_empty() {
+        String::new()
+    } else {
+        format!(r#" style="{}""#, html_escape(&style))
+    }
+}
+
+/// `srcset` candidates for `img`: every thumbnail width it is wider than,
+/// plus the original at its own width if `original` is set. Empty when the
+/// image's size is unknown, since thumbnails never upscale.
+fn srcset(gallery: &Gallery, img: &ImageEntry, original: bool) -> String {
+    let Some(width) = img.width else { return String::new() };
+    let name = urlencode_path(&img.name);
+    let prefix = &gallery.prefix;
+    let mut candidates: Vec<String> = gallery
+        .thumbs
+        .sizes()
+        .iter()
+        .filter(|&&size| size < width)
+        .map(|size| format!("{prefix}/thumbs/{size}/{name} {size}w"))
+        .collect();
+    if original {
+        candidates.push(format!("{prefix}/wallpapers/{name} {width}w"));
+    }
+    candidates.join(", ")
+}
+
+/// ` srcset=".." sizes=".." width=".." height=".."` for an `<img>` showing
+/// `img`, as far as they are known. The width and height only give the
+/// browser the aspect ratio to reserve space with.
+fn responsive_attrs(gallery: &Gallery, img: &ImageEntry, sizes: &str, original: bool) -> String {
+    let mut attrs = String::new();
+    let srcset = srcset(gallery, img, original);
+    if !srcset.is_empty() {
+        attrs.push_str(&format!(r#" srcset="{}" sizes="{sizes}""#, html_escape(&srcset)));
+    }
+    if let (Some(width), Some(height)) = (img.width, img.height) {
+        attrs.push_str(&format!(r#" width="{width}" height="{height}""#));
+    }
+    attrs
+}
+
+/// Format to transcode generated images to: the best one the client accepts,
+/// when negotiation is turned on.
+fn negotiated_format(gallery: &Gallery, req: &Request) -> Option<OutputFormat> {
+    if !gallery.negotiate_formats {
+        return None;
+    }
+    let accept = req.headers().get(header::ACCEPT)?.to_str().ok()?;
+    OutputFormat::negotiate(accept)
+}
+
+/// `?w=&h=&mode=&fx=&fy=&format=` of `/render`.
+#[derive(Deserialize)]
+struct RenderQuery {
+    w: Option<u32>,
+    h: Option<u32>,
+    #[serde(default)]
+    mode: RenderMode,
+    fx: Option<f32>,
+    fy: Option<f32>,
+    format: Option<OutputFormat>,
+}
+
+/// A wallpaper resized or cropped to an exact size, e.g. a phone's screen.
+async fn render(
+    State(gallery): State<Arc<Gallery>>,
+    UrlPath(name): UrlPath<String>,
+    Query(query): Query<RenderQuery>,
+    req: Request,
+) -> Response {
+    let focus = (query.fx.unwrap_or(0.5), query.fy.unwrap_or(0.5));
+    let spec = match RenderSpec::new(query.w, query.h, query.mode, focus, query.format, gallery.max_render_side) {
+        Ok(spec) => spec,
+        Err(e) => return (StatusCode::BAD_REQUEST, e).into_response(),
+    };
+    let Some(image) = gallery.index.get(&name) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    if !image.format.decodable() {
+        let message = format!("cannot render {} images", image.format.name());
+        return (StatusCode::UNSUPPORTED_MEDIA_TYPE, message).into_response();
+    }
+
+    let format = negotiated_format(&gallery, &req);
+    let rendered = gallery.thumbs.render(&gallery.index.path_of(&image), spec, format).await;
+    generated_response(rendered, gallery.negotiate_formats && spec.format.is_none(), req).await
 }
 
 fn styled_page(title: &str, body: &str) -> String {
@@ -294,20 +1337,55 @@ h1 {{ margin: 0; font-size: 1.25rem; }}
 nav {{ display: flex; gap: 10px; }}
 .btn {{ color: var(--bg); background: var(--accent); padding: 8px 12px; border-radius: 8px; text-decoration: none; font-weight: 600; }}
 
-.filters {{ padding: 10px; display: flex; gap: 8px; justify-content: center; }}
+.filters {{ padding: 10px; display: flex; flex-wrap: wrap; gap: 8px; justify-content: center; }}
 .filter-btn {{ background: var(--card); color: var(--fg); border: 1px solid #333; padding: 6px 12px; border-radius: 6px; cursor: pointer; }}
 .filter-btn:hover {{ background: var(--accent); color: var(--bg); }}
+.filter-btn .count {{ color: var(--muted); font-size: 0.85em; margin-left: 4px; }}
+.filter-btn:hover .count {{ color: var(--bg); }}
+.filter-btn.active {{ background: var(--accent); color: var(--bg); border-color: var(--accent); }}
+.filter-btn.active .count {{ color: var(--bg); }}
+.pick {{ background: var(--card); border: 1px solid #333; padding: 6px 10px; border-radius: 6px; cursor: pointer; }}
+.pick:has(input:checked) {{ border-color: var(--accent); }}
+.pick .count {{ color: var(--muted); font-size: 0.85em; }}
+.mode-btn {{ background: transparent; color: var(--muted); border: 1px dashed #444; padding: 6px 12px; border-radius: 6px; cursor: pointer; }}
 
 .grid {{ display: grid; grid-template-columns: repeat(auto-fill, minmax(180px, 1fr)); gap: 12px; padding: 16px; }}
-.card {{ display: block; background: var(--card); border-radius: 10px; overflow: hidden; border: 1px solid #222; }}
+.card {{ position: relative; display: block; background: var(--card) center / cover no-repeat; border-radius: 10px; overflow: hidden; border: 1px solid #222; }}
 .card img {{ display: block; width: 100%; height: 180px; object-fit: cover; }}
+.card .motion {{ position: absolute; top: 0; left: 0; width: 100%; height: 180px; object-fit: cover; }}
+.badge {{ position: absolute; top: 6px; right: 6px; background: rgba(13,17,23,0.8); color: var(--fg); font-size: 0.75rem; padding: 2px 7px; border-radius: 999px; pointer-events: none; }}
+.chips {{ position: absolute; left: 6px; bottom: 6px; display: flex; flex-wrap: wrap; gap: 4px; pointer-events: none; }}
+.chip {{ background: rgba(13,17,23,0.8); color: var(--fg); font-size: 0.7rem; padding: 2px 6px; border-radius: 999px; }}
+
+.pager {{ display: flex; flex-wrap: wrap; gap: 12px; align-items: center; justify-content: center; padding: 8px 16px 24px; color: var(--muted); }}
+.pager.infinite a[rel=prev] {{ display: none; }}
+.galleries {{ display: grid; grid-template-columns: repeat(auto-fill, minmax(260px, 1fr)); gap: 16px; padding: 16px; }}
+.gallery-card {{ display: block; background: var(--card); border-radius: 10px; overflow: hidden; border: 1px solid #222; color: var(--fg); text-decoration: none; }}
+.gallery-card img, .empty-cover {{ display: block; width: 100%; height: 200px; object-fit: cover; background: #222; }}
+.gallery-name {{ display: block; padding: 10px 12px; font-weight: 600; }}
+.breadcrumbs {{ padding: 12px 20px 0; color: var(--muted); }}
+.breadcrumbs a {{ color: var(--accent); text-decoration: none; }}
+.albums .gallery-card img {{ height: 140px; }}
+.gallery-name .count {{ color: var(--muted); font-weight: normal; margin-left: 4px; }}
 
 .random {{ padding: 18px; display: grid; place-items: center; gap: 12px; }}
 .hero {{ width: min(720px, 90vw); height: auto; border-radius: 12px; border: 1px solid #222; box-shadow: 0 8px 24px rgba(0,0,0,0.3); cursor: zoom-in; }}
+video.hero {{ cursor: auto; }}
 .quote {{ font-size: 1.1rem; color: var(--muted); text-align: center; }}
 
-.lightbox {{ display: none; position: fixed; top: 0; left: 0; width: 100%; height: 100%; background: rgba(0,0,0,0.85); justify-content: center; align-items: center; z-index: 1000; }}
+.lightbox {{ display: none; position: fixed; top: 0; left: 0; width: 100%; height: 100%; background: rgba(0,0,0,0.85); flex-direction: column; gap: 10px; justify-content: center; align-items: center; z-index: 1000; }}
+.lightbox-info {{ display: flex; flex-wrap: wrap; gap: 6px 16px; justify-content: center; max-width: 90%; color: var(--fg); font-size: 14px; }}
+.lightbox-info[hidden] {{ display: none; }}
+.lightbox-info a {{ color: var(--accent); }}
+.lightbox-info p {{ flex-basis: 100%; margin: 0; text-align: center; color: var(--muted); }}
 .lightbox-img {{ max-width: 90%; max-height: 90%; border-radius: 8px; }}
+.lightbox-img[hidden] {{ display: none; }}
+.health {{ padding: 0 20px 20px; }}
+.health h2 {{ font-size: 1.1rem; margin: 24px 0 4px; }}
+.health p {{ color: var(--muted); }}
+.report {{ border-collapse: collapse; }}
+.report th, .report td {{ text-align: left; padding: 6px 16px 6px 0; border-bottom: 1px solid #222; }}
+.report a {{ color: var(--accent); }}
 .close {{ position: absolute; top: 20px; right: 30px; font-size: 2rem; color: white; cursor: pointer; }}
 </style>
 </head>
@@ -332,6 +1410,58 @@ fn minimal_page(title: &str, inner: &str) -> String {
     )
 }
 
+/// Nav button back to the gallery index, when there is one.
+fn home_link(gallery: &Gallery) -> &'static str {
+    if gallery.show_index {
+        r#"<a class="btn" href="/">🏠 Galleries</a>"#
+    } else {
+        ""
+    }
+}
+
+fn no_images_page(title: &str, dir: &Path) -> String {
+    minimal_page(
+        title,
+        &format!(
+            "<p>No images found. Add files to <code>{}</code>.</p>",
+            html_escape(&dir.display().to_string())
+        ),
+    )
+}
+
+/// File size for people, e.g. `3.4 MB`.
+fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KB", "MB", "GB", "TB"];
+    if bytes < 1000 {
+        return format!("{bytes} bytes");
+    }
+    let mut size = bytes as f64 / 1000.0;
+    let mut unit = 0;
+    while size >= 1000.0 && unit + 1 < UNITS.len() {
+        size /= 1000.0;
+        unit += 1;
+    }
+    format!("{size:.1} {}", UNITS[unit])
+}
+
+/// Percent-encodes a relative path segment by segment, keeping the slashes.
+fn urlencode_path(path: &str) -> String {
+    path.split('/').map(urlencode).collect::<Vec<_>>().join("/")
+}
+
+/// Percent-encodes a string for use as a URL path segment or query value.
+fn urlencode(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for b in s.bytes() {
+        if b.is_ascii_alphanumeric() || b"-._~".contains(&b) {
+            out.push(b as char);
+        } else {
+            out.push_str(&format!("%{b:02X}"));
+        }
+    }
+    out
+}
+
 fn html_escape(s: &str) -> String {
     s.replace('&', "&amp;")
         .replace('<', "&lt;")
@@ -0,0 +1,77 @@
+use serde::Deserialize;
+use std::{
+    ffi::OsString,
+    fmt,
+    path::{Path, PathBuf},
+};
+
+/// Extensions a sidecar can have, tried in this order: `name.jpg.toml`, ...
+const SIDECAR_EXTENSIONS: [&str; 4] = ["toml", "json", "yaml", "yml"];
+
+/// Credits and description of one image, from a sidecar file next to it.
+#[derive(Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Sidecar {
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    /// Where the image comes from, e.g. the artist's page
+    pub source: Option<String>,
+    pub license: Option<String>,
+    /// Added to the tags from the rules, replacing the fallback tag
+    pub tags: Vec<String>,
+    /// Description for screen readers
+    pub alt: Option<String>,
+}
+
+#[derive(Debug)]
+pub enum SidecarError {
+    Io(std::io::Error),
+    Parse(String),
+}
+
+impl fmt::Display for SidecarError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SidecarError::Io(e) => write!(f, "{e}"),
+            SidecarError::Parse(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl std::error::Error for SidecarError {}
+
+impl Sidecar {
+    /// Reads a sidecar in the format its extension says.
+    pub fn load(path: &Path) -> Result<Self, SidecarError> {
+        let text = std::fs::read_to_string(path).map_err(SidecarError::Io)?;
+        let parsed = match path.extension().and_then(|e| e.to_str()) {
+            Some("json") => serde_json::from_str(&text).map_err(|e| e.to_string()),
+            Some("yaml" | "yml") => serde_yaml::from_str(&text).map_err(|e| e.to_string()),
+            _ => toml::from_str(&text).map_err(|e| e.to_string()),
+        };
+        parsed.map_err(SidecarError::Parse)
+    }
+}
+
+/// The sidecar of `image`, if it has one.
+pub fn sidecar_path(image: &Path) -> Option<PathBuf> {
+    SIDECAR_EXTENSIONS.iter().map(|ext| with_suffix(image, ext)).find(|p| p.is_file())
+}
+
+/// The image a sidecar path belongs to, if `path` looks like one.
+pub fn image_of_sidecar(path: &Path) -> Option<PathBuf> {
+    let ext = path.extension()?.to_str()?;
+    if !SIDECAR_EXTENSIONS.contains(&ext) {
+        return None;
+    }
+    let image = path.with_extension("");
+    image.extension().is_some().then_some(image)
+}
+
+/// `image` with `.ext` added after its own extension.
+fn with_suffix(image: &Path, ext: &str) -> PathBuf {
+    let mut path = OsString::from(image.as_os_str());
+    path.push(".");
+    path.push(ext);
+    PathBuf::from(path)
+}
@@ -0,0 +1,833 @@
+use crate::svg;
+use image::ImageReader;
+use std::{
+    fmt,
+    fs::File,
+    io::{self, BufReader, Read, Seek, SeekFrom},
+    panic::AssertUnwindSafe,
+    path::Path,
+};
+
+/// Bytes read from the start of a file to tell its format
+const HEAD: usize = 512;
+/// Bytes at the end of a file searched for the format's end marker
+const TAIL: u64 = 4096;
+/// Bytes at the start of an AVIF file searched for its size
+const AVIF_HEADER: u64 = 64 << 10;
+/// Biggest SVG that is read in full to check it
+const MAX_SVG: u64 = 16 << 20;
+/// Bytes at the start of a WebM file searched for the video size
+const WEBM_HEADER: u64 = 1 << 20;
+/// Biggest MP4 `moov` box (the index of the media data) read for the size
+const MAX_MOOV: u64 = 16 << 20;
+/// Brands of ISO media files that browsers play as MP4 video
+const MP4_BRANDS: [&[u8; 4]; 11] = [
+    b"isom", b"iso2", b"iso4", b"iso5", b"iso6", b"mp41", b"mp42", b"avc1", b"M4V ", b"mmp4", b"dash",
+];
+
+// EBML element IDs on the way to the size of a WebM video
+const SEGMENT: u64 = 0x1853_8067;
+const TRACKS: u64 = 0x1654_AE6B;
+const TRACK_ENTRY: u64 = 0xAE;
+const VIDEO: u64 = 0xE0;
+const PIXEL_WIDTH: u64 = 0xB0;
+const PIXEL_HEIGHT: u64 = 0xBA;
+const CLUSTER: u64 = 0x1F43_B675;
+
+/// Image and video formats the gallery shows, told apart by their first
+/// bytes rather than by their file names.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    Jpeg,
+    Png,
+    Gif,
+    Webp,
+    Avif,
+    /// JPEG XL
+    Jxl,
+    Bmp,
+    Tiff,
+    Svg,
+    Mp4,
+    Webm,
+}
+
+impl Format {
+    pub const ALL: [Format; 11] = [
+        Format::Jpeg,
+        Format::Png,
+        Format::Gif,
+        Format::Webp,
+        Format::Avif,
+        Format::Jxl,
+        Format::Bmp,
+        Format::Tiff,
+        Format::Svg,
+        Format::Mp4,
+        Format::Webm,
+    ];
+
+    /// The format `head`, the start of a file, belongs to.
+    pub fn sniff(head: &[u8]) -> Option<Self> {
+        if head.starts_with(&[0xFF, 0xD8, 0xFF]) {
+            Some(Format::Jpeg)
+        } else if head.starts_with(b"\x89PNG\r\n\x1a\n") {
+            Some(Format::Png)
+        } else if head.starts_with(b"GIF87a") || head.starts_with(b"GIF89a") {
+            Some(Format::Gif)
+        } else if head.len() >= 12 && head.starts_with(b"RIFF") && &head[8..12] == b"WEBP" {
+            Some(Format::Webp)
+        } else if is_avif(head) {
+            Some(Format::Avif)
+        } else if head.starts_with(&[0xFF, 0x0A]) || head.starts_with(b"\0\0\0\x0cJXL \r\n\x87\n") {
+            Some(Format::Jxl)
+        } else if head.starts_with(b"BM") {
+            Some(Format::Bmp)
+        } else if head.starts_with(b"II*\0") || head.starts_with(b"MM\0*") {
+            Some(Format::Tiff)
+        } else if is_svg(head) {
+            Some(Format::Svg)
+        } else if is_mp4(head) {
+            Some(Format::Mp4)
+        } else if head.starts_with(&[0x1A, 0x45, 0xDF, 0xA3]) && find(&head[..head.len().min(64)], b"webm") {
+            Some(Format::Webm)
+        } else {
+            None
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Format::Jpeg => "JPEG",
+            Format::Png => "PNG",
+            Format::Gif => "GIF",
+            Format::Webp => "WebP",
+            Format::Avif => "AVIF",
+            Format::Jxl => "JPEG XL",
+            Format::Bmp => "BMP",
+            Format::Tiff => "TIFF",
+            Format::Svg => "SVG",
+            Format::Mp4 => "MP4",
+            Format::Webm => "WebM",
+        }
+    }
+
+    pub fn mime(self) -> &'static str {
+        match self {
+            Format::Jpeg => "image/jpeg",
+            Format::Png => "image/png",
+            Format::Gif => "image/gif",
+            Format::Webp => "image/webp",
+            Format::Avif => "image/avif",
+            Format::Jxl => "image/jxl",
+            Format::Bmp => "image/bmp",
+            Format::Tiff => "image/tiff",
+            Format::Svg => "image/svg+xml",
+            Format::Mp4 => "video/mp4",
+            Format::Webm => "video/webm",
+        }
+    }
+
+    /// File extensions this format goes by, in lowercase.
+    pub fn extensions(self) -> &'static [&'static str] {
+        match self {
+            Format::Jpeg => &["jpg", "jpeg"],
+            Format::Png => &["png"],
+            Format::Gif => &["gif"],
+            Format::Webp => &["webp"],
+            Format::Avif => &["avif"],
+            Format::Jxl => &["jxl"],
+            Format::Bmp => &["bmp"],
+            Format::Tiff => &["tif", "tiff"],
+            Format::Svg => &["svg"],
+            Format::Mp4 => &["mp4", "m4v"],
+            Format::Webm => &["webm"],
+        }
+    }
+
+    /// Whether browsers can show the file as it is. The others are sent as
+    /// a converted copy.
+    pub fn browser_native(self) -> bool {
+        !matches!(self, Format::Jxl | Format::Bmp | Format::Tiff)
+    }
+
+    /// Whether the pixels can be decoded here, for thumbnails and renders.
+    /// SVGs are sent as they are; videos give their poster frame, through
+    /// ffmpeg.
+    pub fn decodable(self) -> bool {
+        self != Format::Svg
+    }
+
+    pub fn is_video(self) -> bool {
+        matches!(self, Format::Mp4 | Format::Webm)
+    }
+
+    /// Whether `path` has one of this format's extensions.
+    pub fn matches_extension(self, path: &Path) -> bool {
+        path.extension()
+            .and_then(|e| e.to_str())
+            .is_some_and(|ext| self.extensions().contains(&ext.to_ascii_lowercase().as_str()))
+    }
+
+    fn image_format(self) -> Option<image::ImageFormat> {
+        match self {
+            Format::Jpeg => Some(image::ImageFormat::Jpeg),
+            Format::Png => Some(image::ImageFormat::Png),
+            Format::Gif => Some(image::ImageFormat::Gif),
+            Format::Webp => Some(image::ImageFormat::WebP),
+            Format::Bmp => Some(image::ImageFormat::Bmp),
+            Format::Tiff => Some(image::ImageFormat::Tiff),
+            Format::Avif | Format::Jxl | Format::Svg | Format::Mp4 | Format::Webm => None,
+        }
+    }
+}
+
+/// Why a file named like an image can't be shown.
+#[derive(Debug)]
+pub enum Problem {
+    Io(io::Error),
+    Empty,
+    /// Not an image at all; says what it looks like instead
+    NotImage(&'static str),
+    /// Right magic bytes, but the header makes no sense
+    Header(Format, String),
+    /// The end of the image is missing, e.g. an interrupted download
+    Truncated(Format),
+    /// Checking the file hit a bug; it stays out rather than taking the
+    /// rest of the folder with it
+    Crashed(String),
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Problem::Io(e) => write!(f, "{e}"),
+            Problem::Empty => write!(f, "empty file"),
+            Problem::NotImage(what) => write!(f, "not an image, looks like {what}"),
+            Problem::Header(format, e) => write!(f, "broken {} header: {e}", format.name()),
+            Problem::Truncated(format) => write!(f, "{} file is cut short", format.name()),
+            Problem::Crashed(e) => write!(f, "checking the file crashed: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for Problem {}
+
+impl From<io::Error> for Problem {
+    fn from(e: io::Error) -> Self {
+        Problem::Io(e)
+    }
+}
+
+/// What [`check`] found out about a file.
+pub struct Checked {
+    pub format: Format,
+    /// Missing for SVGs that don't state one
+    pub size: Option<(u32, u32)>,
+    /// A GIF, PNG or WebP with more than one frame
+    pub animated: bool,
+}
+
+/// Checks that `path` holds a whole image or video: its format from the
+/// magic bytes, its pixel size from the header, and that it isn't cut short.
+/// Only the start and the end of the file are read, except for SVGs, which
+/// are parsed in full, the index of MP4 files, wherever it is, and the frames
+/// of GIFs up to the second one.
+pub fn check(path: &Path) -> Result<Checked, Problem> {
+    let mut file = File::open(path)?;
+    let len = file.metadata()?.len();
+    let mut head = Vec::with_capacity(HEAD);
+    (&mut file).take(HEAD as u64).read_to_end(&mut head)?;
+    if head.is_empty() {
+        return Err(Problem::Empty);
+    }
+    let Some(format) = Format::sniff(&head) else {
+        return Err(Problem::NotImage(describe(&head)));
+    };
+    let header = |e: String| Problem::Header(format, e);
+
+    file.rewind()?;
+    let dimensions = match format.image_format() {
+        Some(image_format) => ImageReader::with_format(BufReader::new(&mut file), image_format)
+            .into_dimensions()
+            .map(Some)
+            .map_err(|e| header(e.to_string()))?,
+        None if format == Format::Svg => {
+            if len > MAX_SVG {
+                return Err(header("too big".to_string()));
+            }
+            let size = svg::inspect(&std::fs::read(path)?).map_err(header)?;
+            return Ok(Checked { format, size, animated: false });
+        }
+        None => {
+            let size = match format {
+                Format::Mp4 => mp4_size(&mut file, len)?,
+                _ => {
+                    let mut start = Vec::new();
+                    let limit = if format == Format::Webm { WEBM_HEADER } else { AVIF_HEADER };
+                    (&mut file).take(limit).read_to_end(&mut start)?;
+                    match format {
+                        Format::Avif => avif_size(&start),
+                        Format::Webm => webm_size(&start),
+                        _ => jxl_size(&start),
+                    }
+                }
+            };
+            let what = if format.is_video() { "no video size" } else { "no image size" };
+            Some(size.ok_or_else(|| header(what.to_string()))?)
+        }
+    };
+
+    let mut tail = Vec::new();
+    file.seek(SeekFrom::Start(len.saturating_sub(TAIL)))?;
+    file.read_to_end(&mut tail)?;
+    let complete = match format {
+        // Cameras and phones may append data (previews, videos) after the image
+        Format::Jpeg => find(&tail, &[0xFF, 0xD9]) || jpeg_has_end(&std::fs::read(path)?),
+        Format::Png => find(&tail, b"IEND"),
+        Format::Gif => tail.iter().rev().find(|&&b| b != 0) == Some(&0x3B),
+        Format::Webp => u64::from(u32::from_le_bytes(head[4..8].try_into().unwrap())) + 8 <= len,
+        // The size field is sometimes left at zero
+        Format::Bmp => u64::from(u32::from_le_bytes(head[2..6].try_into().unwrap())) <= len,
+        Format::Avif | Format::Mp4 => boxes_fit(&mut file, len)?,
+        // A bare codestream has nothing to check against
+        Format::Jxl => head.starts_with(&[0xFF, 0x0A]) || boxes_fit(&mut file, len)?,
+        Format::Webm => webm_fits(&head, len),
+        Format::Tiff | Format::Svg => true,
+    };
+    if !complete {
+        return Err(Problem::Truncated(format));
+    }
+    let animated = match format {
+        Format::Gif => gif_animated(&mut file).unwrap_or(false),
+        Format::Png => apng_animated(&mut file).unwrap_or(false),
+        Format::Webp => head.get(12..16) == Some(b"VP8X") && head.get(20).is_some_and(|flags| flags & 0x02 != 0),
+        _ => false,
+    };
+    Ok(Checked { format, size: dimensions, animated })
+}
+
+/// Runs `read`, the checks of one file, turning a panic in one of the
+/// parsers into [`Problem::Crashed`].
+pub fn catch_crash<T>(read: impl FnOnce() -> T) -> Result<T, Problem> {
+    std::panic::catch_unwind(AssertUnwindSafe(read)).map_err(|panic| {
+        let reason = panic
+            .downcast_ref::<&str>()
+            .map(|s| s.to_string())
+            .or_else(|| panic.downcast_ref::<String>().cloned())
+            .unwrap_or_default();
+        Problem::Crashed(reason)
+    })
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> bool {
+    haystack.windows(needle.len()).any(|w| w == needle)
+}
+
+/// Whether the JPEG markers lead to an end of image marker.
+fn jpeg_has_end(data: &[u8]) -> bool {
+    let mut pos = 2;
+    while pos + 1 < data.len() {
+        if data[pos] != 0xFF {
+            return false;
+        }
+        match data[pos + 1] {
+            0xD9 => return true,
+            0xFF => pos += 1,
+            0x01 | 0xD0..=0xD7 => pos += 2,
+            marker => {
+                let Some(len) = data.get(pos + 2..pos + 4) else { return false };
+                pos += 2 + usize::from(u16::from_be_bytes([len[0], len[1]]));
+                if marker == 0xDA {
+                    // Skip the compressed data, where 0xFF is stuffed or a restart marker
+                    while pos + 1 < data.len() && (data[pos] != 0xFF || matches!(data[pos + 1], 0x00 | 0xD0..=0xD7)) {
+                        pos += 1;
+                    }
+                }
+            }
+        }
+    }
+    false
+}
+
+/// An ISO media file (the box structure of HEIF) whose brands include AVIF.
+fn is_avif(head: &[u8]) -> bool {
+    if head.get(4..8) != Some(b"ftyp") {
+        return false;
+    }
+    let size = u32::from_be_bytes(head[..4].try_into().unwrap()) as usize;
+    // Major brand, minor version, then the compatible brands
+    let Some(brands) = head.get(8..size.min(head.len())) else { return false };
+    brands
+        .chunks_exact(4)
+        .enumerate()
+        .any(|(i, brand)| i != 1 && (brand == b"avif" || brand == b"avis"))
+}
+
+/// An ISO media file with an MP4 brand. AVIF files are told apart before.
+fn is_mp4(head: &[u8]) -> bool {
+    if head.get(4..8) != Some(b"ftyp") {
+        return false;
+    }
+    let size = u32::from_be_bytes(head[..4].try_into().unwrap()) as usize;
+    let Some(brands) = head.get(8..size.min(head.len())) else { return false };
+    brands
+        .chunks_exact(4)
+        .enumerate()
+        .any(|(i, brand)| i != 1 && MP4_BRANDS.iter().any(|b| *b == brand))
+}
+
+/// Text that starts like XML and has an `<svg>` element near the top.
+fn is_svg(head: &[u8]) -> bool {
+    let text = String::from_utf8_lossy(head.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(head)).to_ascii_lowercase();
+    let text = text.trim_start();
+    text.starts_with('<') && !text.starts_with("<!doctype html") && !text.starts_with("<html") && text.contains("<svg")
+}
+
+/// Whether every top-level box of an ISO media file ends within the file.
+fn boxes_fit(file: &mut File, len: u64) -> io::Result<bool> {
+    let mut pos = 0;
+    while pos < len {
+        let Some((_, _, size)) = read_box(file, pos, len)? else { return Ok(false) };
+        // A crafted 64-bit size could wrap around and start over
+        match pos.checked_add(size).filter(|&end| end <= len) {
+            Some(end) => pos = end,
+            None => return Ok(false),
+        }
+    }
+    Ok(true)
+}
+
+/// Type, header length and total size of the ISO media box at `pos`, or
+/// `None` if its header makes no sense.
+fn read_box(file: &mut File, pos: u64, len: u64) -> io::Result<Option<([u8; 4], u64, u64)>> {
+    let mut header = [0; 16];
+    file.seek(SeekFrom::Start(pos))?;
+    let read = file.by_ref().take(16).read(&mut header)?;
+    if read < 8 {
+        return Ok(None);
+    }
+    let kind = header[4..8].try_into().unwrap();
+    let (header_len, size) = match u32::from_be_bytes(header[..4].try_into().unwrap()) {
+        // Runs to the end of the file
+        0 => (8, len - pos),
+        1 if read == 16 => (16, u64::from_be_bytes(header[8..].try_into().unwrap())),
+        1 => return Ok(None),
+        size => (8, u64::from(size)),
+    };
+    Ok((size >= header_len).then_some((kind, header_len, size)))
+}
+
+/// The biggest display size among the tracks (`tkhd` boxes) of an MP4 file.
+/// The `moov` box holding them comes after the media data unless the file
+/// was made for streaming, so it is looked for box by box.
+fn mp4_size(file: &mut File, len: u64) -> io::Result<Option<(u32, u32)>> {
+    let mut pos = 0;
+    while pos < len {
+        let Some((kind, header_len, size)) = read_box(file, pos, len)? else { return Ok(None) };
+        if &kind == b"moov" {
+            let mut moov = Vec::new();
+            file.seek(SeekFrom::Start(pos + header_len))?;
+            file.by_ref().take((size - header_len).min(MAX_MOOV)).read_to_end(&mut moov)?;
+            return Ok(tkhd_size(&moov));
+        }
+        match pos.checked_add(size).filter(|&end| end <= len) {
+            Some(end) => pos = end,
+            None => return Ok(None),
+        }
+    }
+    Ok(None)
+}
+
+fn tkhd_size(moov: &[u8]) -> Option<(u32, u32)> {
+    moov.windows(4)
+        .enumerate()
+        .filter(|(_, w)| *w == b"tkhd")
+        .filter_map(|(i, _)| {
+            let data = moov.get(i + 4..)?;
+            let word = |at: usize| data.get(at..at + 4).map(|b| u32::from_be_bytes(b.try_into().unwrap()));
+            // Times are 64 bits wide from version 1 on, which moves the matrix
+            let matrix = if *data.first()? == 0 { 40 } else { 52 };
+            // 16.16 fixed point; audio tracks have none
+            let width = word(matrix + 36)? >> 16;
+            let height = word(matrix + 40)? >> 16;
+            // Phones store portrait videos sideways and turn them with the matrix
+            let turned = word(matrix)? == 0 && word(matrix + 4)? != 0;
+            (width > 0 && height > 0).then_some(if turned { (height, width) } else { (width, height) })
+        })
+        .max_by_key(|&(w, h)| u64::from(w) * u64::from(h))
+}
+
+/// An EBML variable length number at the start of `data`, and its length in
+/// bytes. Element IDs keep the leading length bits, sizes don't.
+fn ebml_number(data: &[u8], keep_marker: bool) -> Option<(u64, usize)> {
+    let first = *data.first()?;
+    let len = first.leading_zeros() as usize + 1;
+    let bytes = data.get(..len).filter(|_| len <= 8)?;
+    let first = if keep_marker { u64::from(first) } else { u64::from(first) & (0xFF >> len) };
+    let value = bytes[1..].iter().fold(first, |v, &b| v << 8 | u64::from(b));
+    Some((value, len))
+}
+
+/// ID, data start and data size of the EBML element at `pos`. The size is
+/// `None` when the file doesn't state it, as in live recordings.
+fn ebml_element(data: &[u8], pos: usize) -> Option<(u64, usize, Option<u64>)> {
+    let (id, id_len) = ebml_number(data.get(pos..)?, true)?;
+    let (size, size_len) = ebml_number(data.get(pos + id_len..)?, false)?;
+    let unknown = size == (1 << (7 * size_len)) - 1;
+    Some((id, pos + id_len + size_len, (!unknown).then_some(size)))
+}
+
+/// Size of the video track of a WebM file, from the track list that comes
+/// before the first frames.
+fn webm_size(data: &[u8]) -> Option<(u32, u32)> {
+    let (mut width, mut height) = (None, None);
+    let mut pos = 0;
+    // Only the elements on the way to the size are entered, the rest skipped
+    while let Some((id, start, size)) = ebml_element(data, pos) {
+        match id {
+            SEGMENT | TRACKS | TRACK_ENTRY | VIDEO => pos = start,
+            CLUSTER => break,
+            _ => {
+                let end = start.checked_add(usize::try_from(size?).ok()?)?;
+                if id == PIXEL_WIDTH || id == PIXEL_HEIGHT {
+                    let value = data.get(start..end)?.iter().fold(0u64, |v, &b| v << 8 | u64::from(b));
+                    let value = u32::try_from(value).ok().filter(|&v| v > 0);
+                    if id == PIXEL_WIDTH { width = value } else { height = value }
+                }
+                pos = end;
+            }
+        }
+        if let (Some(w), Some(h)) = (width, height) {
+            return Some((w, h));
+        }
+    }
+    None
+}
+
+/// Whether the segment of a WebM file, which holds everything but its
+/// header, ends within the file.
+fn webm_fits(head: &[u8], len: u64) -> bool {
+    let Some((_, start, Some(size))) = ebml_element(head, 0) else { return false };
+    let Some(segment) = usize::try_from(size).ok().and_then(|s| start.checked_add(s)) else { return false };
+    match ebml_element(head, segment) {
+        Some((SEGMENT, start, Some(size))) => start as u64 + size <= len,
+        // Recordings that were streamed don't know their size
+        Some((SEGMENT, _, None)) => true,
+        _ => false,
+    }
+}
+
+/// Whether a GIF has a second frame, going from block to block.
+fn gif_animated(file: &mut File) -> io::Result<bool> {
+    file.rewind()?;
+    let mut reader = BufReader::new(file);
+    let mut header = [0; 13];
+    reader.read_exact(&mut header)?;
+    // Global colour table
+    if header[10] & 0x80 != 0 {
+        reader.seek_relative(3 << ((header[10] & 7) + 1))?;
+    }
+    let mut frames = 0;
+    loop {
+        let mut block = [0; 1];
+        reader.read_exact(&mut block)?;
+        match block[0] {
+            // Extension: label, then sub-blocks
+            0x21 => reader.seek_relative(1)?,
+            // Image: position, size and flags, the local colour table and the
+            // LZW code size, then sub-blocks
+            0x2C => {
+                frames += 1;
+                if frames > 1 {
+                    return Ok(true);
+                }
+                let mut descriptor = [0; 9];
+                reader.read_exact(&mut descriptor)?;
+                if descriptor[8] & 0x80 != 0 {
+                    reader.seek_relative(3 << ((descriptor[8] & 7) + 1))?;
+                }
+                reader.seek_relative(1)?;
+            }
+            _ => return Ok(false),
+        }
+        loop {
+            reader.read_exact(&mut block)?;
+            if block[0] == 0 {
+                break;
+            }
+            reader.seek_relative(i64::from(block[0]))?;
+        }
+    }
+}
+
+/// Whether a PNG is an APNG with more than one frame: its animation control
+/// chunk comes before the image data.
+fn apng_animated(file: &mut File) -> io::Result<bool> {
+    let mut pos = 8;
+    loop {
+        let mut chunk = [0; 12];
+        file.seek(SeekFrom::Start(pos))?;
+        file.read_exact(&mut chunk)?;
+        let len = u32::from_be_bytes(chunk[..4].try_into().unwrap());
+        match &chunk[4..8] {
+            b"acTL" => return Ok(u32::from_be_bytes(chunk[8..].try_into().unwrap()) > 1),
+            b"IDAT" | b"IEND" => return Ok(false),
+            // Length, type, data and CRC
+            _ => pos += 12 + u64::from(len),
+        }
+    }
+}
+
+/// The biggest image size (`ispe` property) among the items of an AVIF
+/// file, which is the full image rather than a thumbnail or grid tile.
+fn avif_size(data: &[u8]) -> Option<(u32, u32)> {
+    data.windows(4)
+        .enumerate()
+        .filter(|(_, w)| *w == b"ispe")
+        .filter_map(|(i, _)| {
+            // Version and flags, then width and height
+            let size = data.get(i + 8..i + 16)?;
+            let width = u32::from_be_bytes(size[..4].try_into().unwrap());
+            let height = u32::from_be_bytes(size[4..].try_into().unwrap());
+            (width > 0 && height > 0).then_some((width, height))
+        })
+        .max_by_key(|&(w, h)| u64::from(w) * u64::from(h))
+}
+
+/// Image size from the header of a JPEG XL codestream, bare or in a container.
+fn jxl_size(data: &[u8]) -> Option<(u32, u32)> {
+    let codestream = if data.starts_with(&[0xFF, 0x0A]) {
+        data
+    } else {
+        // Boxes up to the (first part of the) codestream
+        let mut pos = 0;
+        loop {
+            let header = data.get(pos..pos + 8)?;
+            let size = u32::from_be_bytes(header[..4].try_into().unwrap()) as usize;
+            match &header[4..] {
+                b"jxlc" => break data.get(pos + 8..)?,
+                // Partial codestreams start with their index
+                b"jxlp" => break data.get(pos + 12..)?,
+                _ if size >= 8 => pos += size,
+                _ => return None,
+            }
+        }
+    };
+    let mut bits = Bits { data: codestream.get(2..)?, pos: 0 };
+    let small = bits.read(1)? == 1;
+    let dimension = |bits: &mut Bits| -> Option<u32> {
+        if small {
+            return Some((bits.read(5)? + 1) * 8);
+        }
+        let width = [9, 13, 18, 30][bits.read(2)? as usize];
+        Some(bits.read(width)? + 1)
+    };
+    let height = dimension(&mut bits)?;
+    let ratio = bits.read(3)?;
+    let width = match ratio {
+        0 => dimension(&mut bits)?,
+        _ => {
+            let (num, den) = [(1, 1), (12, 10), (4, 3), (3, 2), (16, 9), (5, 4), (2, 1)][ratio as usize - 1];
+            (u64::from(height) * num / den) as u32
+        }
+    };
+    Some((width, height))
+}
+
+/// Reads a bit stream least significant bit first, as JPEG XL stores it.
+struct Bits<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl Bits<'_> {
+    fn read(&mut self, count: u32) -> Option<u32> {
+        let mut value = 0;
+        for i in 0..count {
+            let byte = self.data.get(self.pos / 8)?;
+            value |= u32::from((byte >> (self.pos % 8)) & 1) << i;
+            self.pos += 1;
+        }
+        Some(value)
+    }
+}
+
+/// A guess at what a file that is not an image is, for the quarantine list.
+fn describe(head: &[u8]) -> &'static str {
+    let text = String::from_utf8_lossy(head).trim_start().to_ascii_lowercase();
+    if text.starts_with("<!doctype html") || text.starts_with("<html") {
+        "an HTML page"
+    } else if text.starts_with('<') {
+        "an XML file"
+    } else if text.starts_with('{') || text.starts_with('[') {
+        "a JSON file"
+    } else if head.starts_with(b"PK\x03\x04") {
+        "a zip archive"
+    } else if head.starts_with(b"%PDF") {
+        "a PDF"
+    } else if std::str::from_utf8(head).map_or_else(|e| e.error_len().is_none(), |_| true) {
+        // UTF-8, allowing for a character cut in half at the end
+        "a text file"
+    } else {
+        "an unknown format"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    /// Writes `data` to a file of its own in the temp folder.
+    fn temp_file(name: &str, data: &[u8]) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("rusty-gallery-{}-{name}", std::process::id()));
+        std::fs::write(&path, data).unwrap();
+        path
+    }
+
+    fn check_data(name: &str, data: &[u8]) -> Result<Checked, Problem> {
+        let path = temp_file(name, data);
+        let result = check(&path);
+        std::fs::remove_file(&path).unwrap();
+        result
+    }
+
+    fn iso_box(kind: &[u8; 4], data: &[u8]) -> Vec<u8> {
+        let mut b = (8 + data.len() as u32).to_be_bytes().to_vec();
+        b.extend_from_slice(kind);
+        b.extend_from_slice(data);
+        b
+    }
+
+    /// A box with a 64-bit size, which can be anything.
+    fn large_box(kind: &[u8; 4], size: u64) -> Vec<u8> {
+        let mut b = 1u32.to_be_bytes().to_vec();
+        b.extend_from_slice(kind);
+        b.extend_from_slice(&size.to_be_bytes());
+        b
+    }
+
+    fn avif_start() -> Vec<u8> {
+        let mut data = iso_box(b"ftyp", b"avif\0\0\0\0avifmif1");
+        // Version and flags, width 64, height 48
+        data.extend(iso_box(b"ispe", &[0, 0, 0, 0, 0, 0, 0, 64, 0, 0, 0, 48]));
+        data
+    }
+
+    fn mp4_start() -> Vec<u8> {
+        iso_box(b"ftyp", b"isom\0\0\x02\0isomiso2mp41")
+    }
+
+    /// A `moov` box with one video track of `width` × `height`.
+    fn moov(width: u32, height: u32) -> Vec<u8> {
+        // Version 0: flags, times, track ID, duration, reserved, layer and volume
+        let mut tkhd = vec![0; 40];
+        // Identity matrix
+        for v in [0x10000u32, 0, 0, 0, 0x10000, 0, 0, 0, 0x4000_0000] {
+            tkhd.extend(v.to_be_bytes());
+        }
+        tkhd.extend((width << 16).to_be_bytes());
+        tkhd.extend((height << 16).to_be_bytes());
+        iso_box(b"moov", &iso_box(b"trak", &iso_box(b"tkhd", &tkhd)))
+    }
+
+    #[test]
+    fn mp4_size_after_the_media_data() {
+        let mut data = mp4_start();
+        data.extend(iso_box(b"mdat", &[0; 100]));
+        data.extend(moov(1920, 1080));
+        let checked = check_data("fine.mp4", &data).unwrap();
+        assert_eq!(checked.format, Format::Mp4);
+        assert_eq!(checked.size, Some((1920, 1080)));
+    }
+
+    #[test]
+    fn mp4_box_size_that_wraps_around_is_rejected() {
+        let mut data = mp4_start();
+        let end = data.len() as u64;
+        data.extend(large_box(b"mdat", u64::MAX - end + 1));
+        data.extend(moov(1920, 1080));
+        assert!(matches!(check_data("wrap.mp4", &data), Err(Problem::Header(Format::Mp4, _))));
+    }
+
+    #[test]
+    fn avif_size_and_end() {
+        let checked = check_data("fine.avif", &avif_start()).unwrap();
+        assert_eq!(checked.format, Format::Avif);
+        assert_eq!(checked.size, Some((64, 48)));
+    }
+
+    #[test]
+    fn avif_box_past_the_end_is_truncated() {
+        let mut data = avif_start();
+        data.extend(large_box(b"mdat", 1000));
+        assert!(matches!(check_data("short.avif", &data), Err(Problem::Truncated(Format::Avif))));
+    }
+
+    #[test]
+    fn avif_box_size_that_wraps_around_is_truncated() {
+        let mut data = avif_start();
+        let end = data.len() as u64;
+        data.extend(large_box(b"mdat", u64::MAX - end + 1));
+        data.extend([0; 4]);
+        assert!(matches!(check_data("wrap.avif", &data), Err(Problem::Truncated(Format::Avif))));
+    }
+
+    fn encoded(format: image::ImageFormat) -> Vec<u8> {
+        let img = image::RgbImage::from_fn(32, 32, |x, y| image::Rgb([x as u8 * 8, y as u8 * 8, 100]));
+        let mut out = io::Cursor::new(Vec::new());
+        img.write_to(&mut out, format).unwrap();
+        out.into_inner()
+    }
+
+    #[test]
+    fn renamed_text_files_are_not_images() {
+        let text = check_data("notes.jpg", b"shopping list: eggs, milk\n");
+        assert!(matches!(text, Err(Problem::NotImage("a text file"))));
+        let html = check_data("error.png", b"<!DOCTYPE html><html><body>403 Forbidden</body></html>");
+        assert!(matches!(html, Err(Problem::NotImage("an HTML page"))));
+        assert!(matches!(check_data("empty.gif", b""), Err(Problem::Empty)));
+    }
+
+    #[test]
+    fn whole_images_pass() {
+        for (name, format) in [
+            ("fine.png", image::ImageFormat::Png),
+            ("fine.jpg", image::ImageFormat::Jpeg),
+            ("fine.gif", image::ImageFormat::Gif),
+        ] {
+            let checked = check_data(name, &encoded(format)).unwrap();
+            assert_eq!(checked.size, Some((32, 32)), "{name}");
+        }
+    }
+
+    #[test]
+    fn cut_images_are_truncated() {
+        for (name, format, expected) in [
+            ("cut.png", image::ImageFormat::Png, Format::Png),
+            ("cut.jpg", image::ImageFormat::Jpeg, Format::Jpeg),
+            ("cut.gif", image::ImageFormat::Gif, Format::Gif),
+        ] {
+            let data = encoded(format);
+            let result = check_data(name, &data[..data.len() - 12]);
+            assert!(matches!(result, Err(Problem::Truncated(f)) if f == expected), "{name}");
+        }
+    }
+
+    #[test]
+    fn png_named_jpg_is_mismatched() {
+        let checked = check_data("really-a-png.jpg", &encoded(image::ImageFormat::Png)).unwrap();
+        assert_eq!(checked.format, Format::Png);
+        assert!(!checked.format.matches_extension(Path::new("really-a-png.jpg")));
+        assert!(Format::Jpeg.matches_extension(Path::new("really-a-png.JPG")));
+    }
+
+    #[test]
+    fn panicking_check_is_a_problem() {
+        let crashed = catch_crash(|| -> Result<Checked, Problem> { panic!("bug in a parser") });
+        assert!(matches!(crashed, Err(Problem::Crashed(reason)) if reason == "bug in a parser"));
+        let fine = catch_crash(|| check_data("fine.png", &encoded(image::ImageFormat::Png)));
+        assert!(matches!(fine, Ok(Ok(_))));
+    }
+}
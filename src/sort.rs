@@ -0,0 +1,171 @@
+use crate::index::ImageEntry;
+use rand::{rngs::StdRng, seq::SliceRandom, SeedableRng};
+use serde::Deserialize;
+use std::{cmp::Ordering, iter::Peekable, str::Chars, sync::Arc};
+
+/// Order of the gallery grid, picked with `?sort=`.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SortMode {
+    /// File name, with numbers compared by value (`img2` before `img10`)
+    #[default]
+    Name,
+    Newest,
+    Oldest,
+    /// Biggest files first
+    Size,
+    /// Most pixels first
+    Resolution,
+    /// Widest first, portrait images last
+    Aspect,
+    /// Shuffled, the same way for the same `?seed=`
+    Random,
+}
+
+impl SortMode {
+    pub const ALL: [SortMode; 7] = [
+        SortMode::Name,
+        SortMode::Newest,
+        SortMode::Oldest,
+        SortMode::Size,
+        SortMode::Resolution,
+        SortMode::Aspect,
+        SortMode::Random,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            SortMode::Name => "name",
+            SortMode::Newest => "newest",
+            SortMode::Oldest => "oldest",
+            SortMode::Size => "size",
+            SortMode::Resolution => "resolution",
+            SortMode::Aspect => "aspect",
+            SortMode::Random => "random",
+        }
+    }
+
+    /// Label for the sort menu.
+    pub fn label(self) -> &'static str {
+        match self {
+            SortMode::Name => "Name",
+            SortMode::Newest => "Newest",
+            SortMode::Oldest => "Oldest",
+            SortMode::Size => "File size",
+            SortMode::Resolution => "Resolution",
+            SortMode::Aspect => "Aspect ratio",
+            SortMode::Random => "Random",
+        }
+    }
+
+    /// Sorts `images` in place. Ties, and images missing the compared value
+    /// (those go last), fall back to name order. `seed` only matters for
+    /// [`SortMode::Random`].
+    pub fn sort(self, images: &mut [&Arc<ImageEntry>], seed: u64) {
+        let by_name = |a: &ImageEntry, b: &ImageEntry| natural_cmp(&a.name, &b.name).then_with(|| a.name.cmp(&b.name));
+        match self {
+            SortMode::Name => images.sort_by(|a, b| by_name(a, b)),
+            SortMode::Newest => images.sort_by(|a, b| desc(a.mtime, b.mtime).then_with(|| by_name(a, b))),
+            SortMode::Oldest => images.sort_by(|a, b| asc(a.mtime, b.mtime).then_with(|| by_name(a, b))),
+            SortMode::Size => images.sort_by(|a, b| b.size.cmp(&a.size).then_with(|| by_name(a, b))),
+            SortMode::Resolution => images.sort_by(|a, b| desc(pixels(a), pixels(b)).then_with(|| by_name(a, b))),
+            SortMode::Aspect => images.sort_by(|a, b| {
+                let ratio = match ((a.width, a.height), (b.width, b.height)) {
+                    ((Some(aw), Some(ah)), (Some(bw), Some(bh))) => {
+                        (u64::from(bw) * u64::from(ah)).cmp(&(u64::from(aw) * u64::from(bh)))
+                    }
+                    ((Some(_), Some(_)), _) => Ordering::Less,
+                    (_, (Some(_), Some(_))) => Ordering::Greater,
+                    _ => Ordering::Equal,
+                };
+                ratio.then_with(|| by_name(a, b))
+            }),
+            SortMode::Random => {
+                // Shuffle from a fixed order so the seed alone decides the result
+                images.sort_by(|a, b| a.name.cmp(&b.name));
+                images.shuffle(&mut StdRng::seed_from_u64(seed));
+            }
+        }
+    }
+}
+
+fn pixels(img: &ImageEntry) -> Option<u64> {
+    Some(u64::from(img.width?) * u64::from(img.height?))
+}
+
+/// Ascending, with `None` last.
+fn asc<T: Ord>(a: Option<T>, b: Option<T>) -> Ordering {
+    match (a, b) {
+        (Some(a), Some(b)) => a.cmp(&b),
+        (a, b) => a.is_none().cmp(&b.is_none()),
+    }
+}
+
+/// Descending, with `None` last.
+fn desc<T: Ord>(a: Option<T>, b: Option<T>) -> Ordering {
+    match (a, b) {
+        (Some(a), Some(b)) => b.cmp(&a),
+        (a, b) => asc(a, b),
+    }
+}
+
+/// Case-insensitive comparison that reads runs of digits as numbers, so
+/// `img2.jpg` < `img10.jpg` and `a.png` < `Z.png`.
+pub fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let (mut a, mut b) = (a.chars().peekable(), b.chars().peekable());
+    loop {
+        let ord = match (a.peek().copied(), b.peek().copied()) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(x), Some(y)) if x.is_ascii_digit() && y.is_ascii_digit() => {
+                let (x, y) = (digits(&mut a), digits(&mut b));
+                let (xt, yt) = (x.trim_start_matches('0'), y.trim_start_matches('0'));
+                // Without leading zeros, a longer number is a bigger one
+                xt.len().cmp(&yt.len()).then_with(|| xt.cmp(yt)).then_with(|| x.len().cmp(&y.len()))
+            }
+            (Some(x), Some(y)) => {
+                a.next();
+                b.next();
+                x.to_lowercase().cmp(y.to_lowercase())
+            }
+        };
+        if ord != Ordering::Equal {
+            return ord;
+        }
+    }
+}
+
+fn digits(chars: &mut Peekable<Chars>) -> String {
+    let mut run = String::new();
+    while let Some(c) = chars.next_if(char::is_ascii_digit) {
+        run.push(c);
+    }
+    run
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn numbers_compare_by_value() {
+        assert_eq!(natural_cmp("img2.jpg", "img10.jpg"), Ordering::Less);
+        assert_eq!(natural_cmp("img10.jpg", "img2.jpg"), Ordering::Greater);
+        assert_eq!(natural_cmp("img02.jpg", "img2.jpg"), Ordering::Greater);
+        assert_eq!(natural_cmp("v1.10", "v1.9"), Ordering::Greater);
+    }
+
+    #[test]
+    fn case_is_ignored() {
+        assert_eq!(natural_cmp("a.png", "Z.png"), Ordering::Less);
+        assert_eq!(natural_cmp("Photo.png", "photo.png"), Ordering::Equal);
+    }
+
+    #[test]
+    fn sorts_a_list() {
+        let mut names = vec!["img10.jpg", "Z.png", "img2.jpg", "a.png", "img1.jpg"];
+        names.sort_by(|a, b| natural_cmp(a, b));
+        assert_eq!(names, ["a.png", "img1.jpg", "img2.jpg", "img10.jpg", "Z.png"]);
+    }
+}
@@ -0,0 +1,671 @@
+use crate::{embedded::itxt_xmp, sniff::Format, svg, thumbs::cache_key};
+use exif::{Context, Field, In, Tag, Value};
+use regex::Regex;
+use serde::Deserialize;
+use std::{
+    collections::HashSet,
+    fs::File,
+    io::{self, Cursor, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+    sync::{LazyLock, Mutex},
+};
+
+/// XMP properties that give away where a picture was taken or with which device
+static PRIVATE_XMP: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"[\w-]+:(GPS[A-Za-z]+|\w*SerialNumber|\w*OwnerName)\b").unwrap());
+
+/// Biggest MP4 index (`moov` box) read to find the metadata in it
+const MAX_MP4_INDEX: u64 = 64 << 20;
+
+/// What to remove from wallpapers before they are sent.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StripPolicy {
+    /// Serve the files as they are
+    None,
+    /// GPS position, serial numbers, owner name and maker notes, and the
+    /// location of MP4 videos
+    #[default]
+    Private,
+    /// Every metadata block except colour profiles, and the user data and
+    /// metadata boxes of MP4 videos
+    All,
+}
+
+/// Copies of wallpapers with their metadata stripped, and SVGs with anything
+/// that could run code taken out, made on first request and kept on disk.
+/// The originals are never touched.
+pub struct Stripper {
+    dir: PathBuf,
+    policy: StripPolicy,
+    /// Cache keys of files that had nothing to strip, served as they are
+    clean: Mutex<HashSet<String>>,
+}
+
+impl Stripper {
+    /// Stripped copies go to `cache_dir/stripped`.
+    pub fn new(cache_dir: &Path, policy: StripPolicy) -> Self {
+        Stripper {
+            dir: cache_dir.join("stripped"),
+            policy,
+            clean: Mutex::new(HashSet::new()),
+        }
+    }
+
+    /// Path of the file to serve for `source`: the original if there is
+    /// nothing to strip, else a stripped copy, creating it first if needed.
+    /// SVGs are always cleaned, whatever the policy; WebM videos, which have
+    /// no standard place for a location, are sent as they are.
+    pub async fn get(&self, source: &Path, format: Format) -> std::io::Result<PathBuf> {
+        if (self.policy == StripPolicy::None && format != Format::Svg) || format == Format::Webm {
+            return Ok(source.to_path_buf());
+        }
+        let key = cache_key(source, self.policy).await?;
+        if self.clean.lock().unwrap().contains(&key) {
+            return Ok(source.to_path_buf());
+        }
+        // Keep the extension, the content type is guessed from it
+        let mut path = self.dir.join(&key);
+        if let Some(ext) = source.extension() {
+            path.set_extension(ext);
+        }
+        if tokio::fs::try_exists(&path).await? {
+            return Ok(path);
+        }
+        if format == Format::Mp4 {
+            return self.strip_mp4(source, key, path).await;
+        }
+
+        let data = tokio::fs::read(source).await?;
+        let policy = self.policy;
+        let stripped = tokio::task::spawn_blocking(move || strip(&data, policy))
+            .await
+            .map_err(std::io::Error::other)??;
+        let Some(stripped) = stripped else {
+            self.clean.lock().unwrap().insert(key);
+            return Ok(source.to_path_buf());
+        };
+        tokio::fs::create_dir_all(&self.dir).await?;
+        // Written under a temporary name so a concurrent request never sees half a file
+        let tmp = self.dir.join(format!("{key}.{:08x}.tmp", rand::random::<u32>()));
+        if let Err(e) = tokio::fs::write(&tmp, stripped).await {
+            let _ = tokio::fs::remove_file(&tmp).await;
+            return Err(e);
+        }
+        tokio::fs::rename(&tmp, &path).await?;
+        Ok(path)
+    }
+
+    /// Videos can be far too big to read whole, so the source is copied and
+    /// the boxes to remove are renamed to `free` in the copy.
+    async fn strip_mp4(&self, source: &Path, key: String, path: PathBuf) -> io::Result<PathBuf> {
+        let (file, policy) = (source.to_path_buf(), self.policy);
+        let boxes = tokio::task::spawn_blocking(move || mp4_boxes_to_free(&mut File::open(file)?, policy))
+            .await
+            .map_err(std::io::Error::other)??;
+        if boxes.is_empty() {
+            self.clean.lock().unwrap().insert(key);
+            return Ok(source.to_path_buf());
+        }
+        tokio::fs::create_dir_all(&self.dir).await?;
+        let tmp = self.dir.join(format!("{key}.{:08x}.tmp", rand::random::<u32>()));
+        let written = async {
+            use tokio::io::{AsyncSeekExt, AsyncWriteExt};
+            tokio::fs::copy(source, &tmp).await?;
+            let mut copy = tokio::fs::OpenOptions::new().write(true).open(&tmp).await?;
+            for offset in boxes {
+                copy.seek(SeekFrom::Start(offset + 4)).await?;
+                copy.write_all(b"free").await?;
+            }
+            copy.flush().await
+        };
+        if let Err(e) = written.await {
+            let _ = tokio::fs::remove_file(&tmp).await;
+            return Err(e);
+        }
+        tokio::fs::rename(&tmp, &path).await?;
+        Ok(path)
+    }
+}
+
+/// `data` without the metadata `policy` removes, or `None` if there is
+/// nothing to remove. Only the container is rebuilt; the compressed image
+/// data is copied as it is. SVGs are sanitized, other formats than JPEG, PNG
+/// and WebP are left alone, and anything after the end of the image is
+/// dropped. A JPEG whose end can't be found is an error, rather than sent
+/// cut short or with its metadata.
+pub fn strip(data: &[u8], policy: StripPolicy) -> io::Result<Option<Vec<u8>>> {
+    // Looked at again, the file may have changed since it was indexed
+    let Some(format) = Format::sniff(data) else { return Ok(None) };
+    let stripped = match (format, policy) {
+        (Format::Svg, _) => svg::sanitize(data, policy == StripPolicy::All),
+        (_, StripPolicy::None) => return Ok(None),
+        (Format::Jpeg, _) => strip_jpeg(data, policy)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "the JPEG markers don't lead to its end"))?,
+        (Format::Png, _) => strip_png(data, policy),
+        (Format::Webp, _) => strip_webp(data, policy),
+        _ => return Ok(None),
+    };
+    Ok((stripped != data).then_some(stripped))
+}
+
+/// What to do with one metadata block.
+enum Action {
+    Keep,
+    Drop,
+    Replace(Vec<u8>),
+}
+
+/// The JPEG without the segments `policy` removes, or `None` if the markers
+/// don't lead to the end of the image.
+fn strip_jpeg(data: &[u8], policy: StripPolicy) -> Option<Vec<u8>> {
+    let mut out = Vec::with_capacity(data.len());
+    out.extend_from_slice(&data[..2]);
+    let mut pos = 2;
+    while pos + 1 < data.len() {
+        // Padding or junk between segments, which decoders skip too
+        if data[pos] != 0xFF {
+            pos += 1;
+            continue;
+        }
+        let marker = data[pos + 1];
+        // Fill bytes before a marker
+        if marker == 0xFF {
+            pos += 1;
+            continue;
+        }
+        if marker == 0x00 {
+            pos += 2;
+            continue;
+        }
+        if marker == 0xD9 {
+            out.extend_from_slice(&[0xFF, 0xD9]);
+            return Some(out);
+        }
+        if matches!(marker, 0x01 | 0xD0..=0xD7) {
+            out.extend_from_slice(&[0xFF, marker]);
+            pos += 2;
+            continue;
+        }
+        let len = data.get(pos + 2..pos + 4).map(|l| usize::from(u16::from_be_bytes([l[0], l[1]])))?;
+        let segment = data.get(pos..pos + 2 + len).filter(|_| len >= 2)?;
+        pos += 2 + len;
+        match jpeg_segment(marker, &segment[4..], policy) {
+            Action::Keep => out.extend_from_slice(segment),
+            Action::Drop => {}
+            Action::Replace(payload) => {
+                // Too big for a segment after all: leave it out
+                let Ok(len) = u16::try_from(payload.len() + 2) else { continue };
+                out.extend_from_slice(&[0xFF, marker]);
+                out.extend_from_slice(&len.to_be_bytes());
+                out.extend_from_slice(&payload);
+            }
+        }
+        if marker == 0xDA {
+            // Entropy-coded data runs up to the next marker that isn't a
+            // stuffed 0xFF or a restart marker
+            let start = pos;
+            while pos < data.len() {
+                if data[pos] == 0xFF && pos + 1 < data.len() && !matches!(data[pos + 1], 0x00 | 0xD0..=0xD7) {
+                    break;
+                }
+                pos += 1;
+            }
+            out.extend_from_slice(&data[start..pos]);
+        }
+    }
+    None
+}
+
+fn jpeg_segment(marker: u8, payload: &[u8], policy: StripPolicy) -> Action {
+    match (marker, policy) {
+        (0xE1, StripPolicy::All) => Action::Drop,
+        (0xE1, _) => {
+            if let Some(tiff) = payload.strip_prefix(b"Exif\0\0") {
+                match clean_exif(tiff) {
+                    Action::Replace(tiff) => Action::Replace([b"Exif\0\0".as_slice(), &tiff].concat()),
+                    action => action,
+                }
+            } else if let Some(xmp) = payload.strip_prefix(b"http://ns.adobe.com/xap/1.0/\0") {
+                xmp_action(xmp, policy)
+            } else if payload.starts_with(b"http://ns.adobe.com/xmp/extension/\0") {
+                // Split over several segments, so it can't be checked piece by piece
+                Action::Drop
+            } else {
+                Action::Keep
+            }
+        }
+        // Colour profile
+        (0xE2, _) if payload.starts_with(b"ICC_PROFILE\0") => Action::Keep,
+        // Photoshop resources (IPTC) and comments
+        (0xED | 0xFE, StripPolicy::All) => Action::Drop,
+        // Other application data, except JFIF and Adobe's colour transform
+        (0xE2..=0xEC | 0xEF, StripPolicy::All) => Action::Drop,
+        _ => Action::Keep,
+    }
+}
+
+fn strip_png(data: &[u8], policy: StripPolicy) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len());
+    out.extend_from_slice(&data[..8]);
+    let mut pos = 8;
+    while let Some(header) = data.get(pos..pos + 8) {
+        let len = u32::from_be_bytes(header[..4].try_into().unwrap()) as usize;
+        let kind: [u8; 4] = header[4..].try_into().unwrap();
+        let Some(chunk) = data.get(pos..pos + 12 + len) else { break };
+        pos += 12 + len;
+        match png_chunk(&kind, &chunk[8..8 + len], policy) {
+            Action::Keep => out.extend_from_slice(chunk),
+            Action::Drop => {}
+            Action::Replace(body) => {
+                let Ok(len) = u32::try_from(body.len()) else { continue };
+                out.extend_from_slice(&len.to_be_bytes());
+                out.extend_from_slice(&kind);
+                out.extend_from_slice(&body);
+                let mut crc = crc32fast::Hasher::new();
+                crc.update(&kind);
+                crc.update(&body);
+                out.extend_from_slice(&crc.finalize().to_be_bytes());
+            }
+        }
+        if &kind == b"IEND" {
+            break;
+        }
+    }
+    out
+}
+
+fn png_chunk(kind: &[u8; 4], body: &[u8], policy: StripPolicy) -> Action {
+    match (kind, policy) {
+        (b"eXIf" | b"tEXt" | b"zTXt" | b"iTXt" | b"tIME", StripPolicy::All) => Action::Drop,
+        (b"eXIf", _) => clean_exif(body),
+        (b"iTXt", _) => match itxt_xmp(body) {
+            Some(Some(xmp)) => xmp_action(xmp, policy),
+            // Compressed XMP can't be checked
+            Some(None) => Action::Drop,
+            None => Action::Keep,
+        },
+        // ImageMagick's hex dumps of EXIF and XMP
+        (b"tEXt" | b"zTXt", _) if body.starts_with(b"Raw profile type ") => Action::Drop,
+        _ => Action::Keep,
+    }
+}
+
+fn strip_webp(data: &[u8], policy: StripPolicy) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len());
+    out.extend_from_slice(&data[..12]);
+    let mut removed = 0u8;
+    let mut pos = 12;
+    while let Some(header) = data.get(pos..pos + 8) {
+        let len = u32::from_le_bytes(header[4..].try_into().unwrap()) as usize;
+        let kind: [u8; 4] = header[..4].try_into().unwrap();
+        // Chunks are padded to an even size
+        let Some(chunk) = data.get(pos..pos + 8 + len + len % 2) else { break };
+        pos += chunk.len();
+        let action = match (&kind, policy) {
+            (b"EXIF" | b"XMP ", StripPolicy::All) => Action::Drop,
+            (b"EXIF", _) => {
+                let body = &chunk[8..8 + len];
+                match body.strip_prefix(b"Exif\0\0") {
+                    Some(tiff) => match clean_exif(tiff) {
+                        Action::Replace(tiff) => Action::Replace([b"Exif\0\0".as_slice(), &tiff].concat()),
+                        action => action,
+                    },
+                    None => clean_exif(body),
+                }
+            }
+            (b"XMP ", _) => xmp_action(&chunk[8..8 + len], policy),
+            _ => Action::Keep,
+        };
+        match action {
+            Action::Keep => out.extend_from_slice(chunk),
+            Action::Drop => {
+                removed |= match &kind {
+                    b"EXIF" => 0x08,
+                    _ => 0x04,
+                }
+            }
+            Action::Replace(body) => {
+                let Ok(len) = u32::try_from(body.len()) else { continue };
+                out.extend_from_slice(&kind);
+                out.extend_from_slice(&len.to_le_bytes());
+                out.extend_from_slice(&body);
+                if body.len() % 2 == 1 {
+                    out.push(0);
+                }
+            }
+        }
+    }
+    // The extended header announces which metadata chunks follow
+    if removed != 0 && out.get(12..16) == Some(b"VP8X") && out.len() > 20 {
+        out[20] &= !removed;
+    }
+    let size = u32::try_from(out.len() - 8).unwrap_or(u32::MAX);
+    out[4..8].copy_from_slice(&size.to_le_bytes());
+    out
+}
+
+/// Offsets of the boxes in an MP4 that `policy` removes. Renamed to `free`,
+/// they keep their size, so the offsets to the media data stay right and
+/// only the index has to be read.
+fn mp4_boxes_to_free(file: &mut (impl Read + Seek), policy: StripPolicy) -> io::Result<Vec<u64>> {
+    let len = file.seek(SeekFrom::End(0))?;
+    let mut found = Vec::new();
+    let mut pos = 0;
+    while pos < len {
+        file.seek(SeekFrom::Start(pos))?;
+        let mut head = Vec::with_capacity(16);
+        file.by_ref().take(16).read_to_end(&mut head)?;
+        // A box cut short ends the file as far as players are concerned
+        let Some((size, kind, header)) = mp4_box_header(&head, len - pos) else { break };
+        if matches!(&kind, b"moov" | b"udta" | b"meta") {
+            if size > MAX_MP4_INDEX {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "the MP4 index is too big to check"));
+            }
+            let mut body = vec![0; (size - header) as usize];
+            file.seek(SeekFrom::Start(pos + header))?;
+            file.read_exact(&mut body)?;
+            mp4_box(&kind, &body, pos, pos + header, policy, &mut found);
+        }
+        pos += size;
+    }
+    Ok(found)
+}
+
+/// Size, type and header length of the box `head` starts with, if it fits in
+/// the `room` left.
+fn mp4_box_header(head: &[u8], room: u64) -> Option<(u64, [u8; 4], u64)> {
+    let kind: [u8; 4] = head.get(4..8)?.try_into().unwrap();
+    let (size, header) = match u32::from_be_bytes(head[..4].try_into().unwrap()) {
+        0 => (room, 8),
+        1 => (u64::from_be_bytes(head.get(8..16)?.try_into().unwrap()), 16),
+        size => (u64::from(size), 8),
+    };
+    (header <= size && size <= room).then_some((size, kind, header))
+}
+
+/// The boxes in `body`: offset, type, offset of the body and body of each.
+fn mp4_children(body: &[u8]) -> impl Iterator<Item = (u64, [u8; 4], u64, &[u8])> {
+    let mut at = 0;
+    std::iter::from_fn(move || {
+        let (size, kind, header) = mp4_box_header(&body[at..], (body.len() - at) as u64)?;
+        let child = (at as u64, kind, at as u64 + header, &body[at + header as usize..at + size as usize]);
+        at += size as usize;
+        Some(child)
+    })
+}
+
+/// Adds the offsets of the boxes to free to `found`, for a box at `offset`
+/// whose body starts at `body_at`.
+fn mp4_box(kind: &[u8; 4], body: &[u8], offset: u64, body_at: u64, policy: StripPolicy, found: &mut Vec<u64>) {
+    match kind {
+        b"moov" | b"trak" => {
+            for (at, kind, child_at, child) in mp4_children(body) {
+                mp4_box(&kind, child, body_at + at, body_at + child_at, policy, found);
+            }
+        }
+        b"udta" | b"meta" if policy == StripPolicy::All => found.push(offset),
+        // QuickTime's ISO 6709 position
+        b"udta" => found.extend(
+            mp4_children(body).filter(|(_, kind, ..)| kind == b"\xA9xyz").map(|(at, ..)| body_at + at),
+        ),
+        // Apple's key-value metadata, where the location is one of the keys
+        b"meta" if find(body, b"location") || find(body, b"\xA9xyz") => found.push(offset),
+        _ => {}
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> bool {
+    haystack.windows(needle.len()).any(|w| w == needle)
+}
+
+fn xmp_action(xmp: &[u8], policy: StripPolicy) -> Action {
+    // Rewriting the XML is not worth the risk; a packet with anything
+    // private in it goes as a whole
+    if policy == StripPolicy::All || PRIVATE_XMP.is_match(&String::from_utf8_lossy(xmp)) {
+        Action::Drop
+    } else {
+        Action::Keep
+    }
+}
+
+/// The TIFF structure of an EXIF block rewritten without the private
+/// fields. Only the main image's fields survive, so an embedded thumbnail
+/// (which may show the uncropped picture) goes too.
+fn clean_exif(tiff: &[u8]) -> Action {
+    let Ok(exif) = exif::Reader::new().read_raw(tiff.to_vec()) else {
+        // Can't tell what is in it
+        return Action::Drop;
+    };
+    let is_pointer = |f: &Field| {
+        matches!(f.tag, Tag::ExifIFDPointer | Tag::GPSInfoIFDPointer | Tag::InteropIFDPointer)
+    };
+    let keep = |f: &Field| {
+        f.ifd_num == In::PRIMARY
+            && f.tag.context() != Context::Gps
+            && !matches!(
+                f.tag,
+                Tag::MakerNote | Tag::BodySerialNumber | Tag::LensSerialNumber | Tag::CameraOwnerName
+            )
+            // DNG camera serial number
+            && f.tag != Tag(Context::Tiff, 0xC62F)
+            && !matches!(f.value, Value::Unknown(..))
+    };
+    if exif.fields().all(|f| is_pointer(f) || keep(f)) {
+        return Action::Keep;
+    }
+    let kept: Vec<&Field> = exif.fields().filter(|f| !is_pointer(f) && keep(f)).collect();
+    if kept.is_empty() {
+        return Action::Drop;
+    }
+    let mut writer = exif::experimental::Writer::new();
+    for field in kept {
+        writer.push_field(field);
+    }
+    let mut out = Cursor::new(Vec::new());
+    match writer.write(&mut out, exif.little_endian()) {
+        Ok(()) => Action::Replace(out.into_inner()),
+        Err(_) => Action::Drop,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use exif::Rational;
+    use image::{codecs::jpeg::JpegEncoder, RgbImage};
+
+    const PRIVATE_XMP_PACKET: &[u8] =
+        b"<x:xmpmeta><rdf:Description exif:GPSLatitude=\"48,51.4N\" dc:creator=\"me\"/></x:xmpmeta>";
+
+    fn ascii(tag: Tag, text: &str) -> Field {
+        Field { tag, ifd_num: In::PRIMARY, value: Value::Ascii(vec![text.as_bytes().to_vec()]) }
+    }
+
+    /// TIFF structure with a camera make, a serial number and a GPS position.
+    fn private_exif() -> Vec<u8> {
+        let latitude = Field {
+            tag: Tag::GPSLatitude,
+            ifd_num: In::PRIMARY,
+            value: Value::Rational(vec![Rational::from((48, 1)), Rational::from((51, 1)), Rational::from((24, 1))]),
+        };
+        let fields = [
+            ascii(Tag::Make, "Camera Co"),
+            ascii(Tag::BodySerialNumber, "SN-12345"),
+            ascii(Tag::GPSLatitudeRef, "N"),
+            latitude,
+        ];
+        let mut writer = exif::experimental::Writer::new();
+        for field in &fields {
+            writer.push_field(field);
+        }
+        let mut out = Cursor::new(Vec::new());
+        writer.write(&mut out, false).unwrap();
+        out.into_inner()
+    }
+
+    fn assert_private_fields_gone(tiff: Vec<u8>) {
+        let exif = exif::Reader::new().read_raw(tiff).unwrap();
+        assert!(exif.get_field(Tag::Make, In::PRIMARY).is_some());
+        assert!(exif.get_field(Tag::BodySerialNumber, In::PRIMARY).is_none());
+        assert!(exif.fields().all(|f| f.tag.context() != Context::Gps));
+    }
+
+    fn jpeg() -> Vec<u8> {
+        let img = RgbImage::from_fn(16, 16, |x, y| image::Rgb([x as u8 * 16, y as u8 * 16, 128]));
+        let mut out = Vec::new();
+        img.write_with_encoder(JpegEncoder::new_with_quality(&mut out, 90)).unwrap();
+        out
+    }
+
+    fn jpeg_segment_bytes(marker: u8, payload: &[u8]) -> Vec<u8> {
+        let len = u16::try_from(payload.len() + 2).unwrap();
+        [&[0xFF, marker], &len.to_be_bytes()[..], payload].concat()
+    }
+
+    #[test]
+    fn jpeg_loses_private_fields_and_keeps_its_pixels() {
+        let clean = jpeg();
+        let exif = jpeg_segment_bytes(0xE1, &[b"Exif\0\0".as_slice(), &private_exif()].concat());
+        let xmp = jpeg_segment_bytes(0xE1, &[b"http://ns.adobe.com/xap/1.0/\0".as_slice(), PRIVATE_XMP_PACKET].concat());
+        let data = [&clean[..2], &exif, &xmp, &clean[2..]].concat();
+
+        let stripped = strip(&data, StripPolicy::Private).unwrap().unwrap();
+        // Everything the encoder wrote, entropy-coded data included, is untouched
+        assert!(stripped.ends_with(&clean[2..]));
+        assert!(!find(&stripped, b"GPSLatitude") && !find(&stripped, b"SN-12345"));
+        let exif = exif::Reader::new().read_from_container(&mut Cursor::new(&stripped)).unwrap();
+        assert_private_fields_gone(exif.buf().to_vec());
+
+        let bare = strip(&data, StripPolicy::All).unwrap().unwrap();
+        assert_eq!(bare, clean);
+    }
+
+    #[test]
+    fn jpeg_padding_between_segments_is_skipped() {
+        let clean = jpeg();
+        let exif = jpeg_segment_bytes(0xE1, &[b"Exif\0\0".as_slice(), &private_exif()].concat());
+        let data = [&clean[..2], &exif, &[0, 0, 0x0A], &clean[2..]].concat();
+        let bare = strip(&data, StripPolicy::All).unwrap().unwrap();
+        assert_eq!(bare, clean);
+    }
+
+    #[test]
+    fn jpeg_without_an_end_is_an_error() {
+        let clean = jpeg();
+        let exif = jpeg_segment_bytes(0xE1, &[b"Exif\0\0".as_slice(), &private_exif()].concat());
+        // Cut in the middle of a segment
+        let data = [&clean[..2], &exif, &clean[2..40]].concat();
+        assert!(strip(&data, StripPolicy::Private).is_err());
+    }
+
+    /// A WebP with an extended header announcing EXIF and XMP chunks.
+    fn webp() -> (Vec<u8>, Vec<u8>) {
+        let img = RgbImage::from_fn(16, 16, |x, y| image::Rgb([x as u8 * 16, y as u8 * 16, 128]));
+        let simple = webp::Encoder::from_rgb(&img, 16, 16).encode(80.0).to_vec();
+        let image_chunk = simple[12..].to_vec();
+        let chunk = |kind: &[u8; 4], body: &[u8]| {
+            let mut c = [kind.as_slice(), &(body.len() as u32).to_le_bytes(), body].concat();
+            if body.len() % 2 == 1 {
+                c.push(0);
+            }
+            c
+        };
+        // Flags (EXIF and XMP), reserved, canvas width and height minus one
+        let vp8x = chunk(b"VP8X", &[0x0C, 0, 0, 0, 15, 0, 0, 15, 0, 0]);
+        let exif = chunk(b"EXIF", &private_exif());
+        let xmp = chunk(b"XMP ", PRIVATE_XMP_PACKET);
+        let body = [b"WEBP".as_slice(), &vp8x, &image_chunk, &exif, &xmp].concat();
+        let data = [b"RIFF".as_slice(), &(body.len() as u32).to_le_bytes(), &body].concat();
+        (data, image_chunk)
+    }
+
+    #[test]
+    fn webp_loses_private_fields_and_keeps_its_pixels() {
+        let (data, image_chunk) = webp();
+        let stripped = strip(&data, StripPolicy::Private).unwrap().unwrap();
+        assert!(find(&stripped, &image_chunk));
+        assert_eq!(u32::from_le_bytes(stripped[4..8].try_into().unwrap()) as usize, stripped.len() - 8);
+
+        let exif_at = stripped.windows(4).position(|w| w == b"EXIF").unwrap();
+        let len = u32::from_le_bytes(stripped[exif_at + 4..exif_at + 8].try_into().unwrap()) as usize;
+        assert_private_fields_gone(stripped[exif_at + 8..exif_at + 8 + len].to_vec());
+        assert!(!find(&stripped, b"XMP "));
+    }
+
+    #[test]
+    fn webp_flags_follow_the_removed_chunks() {
+        let (data, _) = webp();
+        // The EXIF chunk is rewritten, the XMP one dropped
+        let private = strip(&data, StripPolicy::Private).unwrap().unwrap();
+        assert_eq!(private[20], 0x08);
+        let bare = strip(&data, StripPolicy::All).unwrap().unwrap();
+        assert_eq!(bare[20], 0x00);
+        assert_eq!(u32::from_le_bytes(bare[4..8].try_into().unwrap()) as usize, bare.len() - 8);
+    }
+
+    #[test]
+    fn nothing_to_strip() {
+        assert_eq!(strip(&jpeg(), StripPolicy::Private).unwrap(), None);
+        let (data, _) = webp();
+        assert_eq!(strip(&data, StripPolicy::None).unwrap(), None);
+    }
+
+    fn mp4_box(kind: &[u8; 4], body: &[u8]) -> Vec<u8> {
+        let mut out = (8 + body.len() as u32).to_be_bytes().to_vec();
+        out.extend_from_slice(kind);
+        out.extend_from_slice(body);
+        out
+    }
+
+    /// An MP4 with a location in its user data and in Apple's metadata, and
+    /// the offsets of the `©xyz`, `udta`, `meta` and track `udta` boxes.
+    fn mp4() -> (Vec<u8>, [u64; 4]) {
+        let xyz = mp4_box(b"\xA9xyz", b"\0\x12\x15\xC7+48.8577+002.2950/");
+        let udta = mp4_box(b"udta", &[xyz, mp4_box(b"\xA9nam", b"Eiffel")].concat());
+        let meta = mp4_box(b"meta", &mp4_box(b"keys", b"\0\0\0\0\0\0\0\x01com.apple.quicktime.location.ISO6709"));
+        let trak = mp4_box(b"trak", &[mp4_box(b"tkhd", &[0; 84]), mp4_box(b"udta", b"")].concat());
+        let moov = mp4_box(b"moov", &[mp4_box(b"mvhd", &[0; 100]), udta.clone(), meta.clone(), trak].concat());
+        let ftyp = mp4_box(b"ftyp", b"isom\0\0\x02\0isomiso2mp41");
+        let data = [ftyp.clone(), moov, mp4_box(b"mdat", &[0xAA; 64])].concat();
+        let udta_at = (ftyp.len() + 8 + 108) as u64;
+        let meta_at = udta_at + udta.len() as u64;
+        (data, [udta_at + 8, udta_at, meta_at, meta_at + meta.len() as u64 + 8 + 92])
+    }
+
+    #[test]
+    fn mp4_location_boxes() {
+        let (data, [xyz, udta, meta, track_udta]) = mp4();
+        for at in [xyz, udta, meta, track_udta] {
+            assert!(matches!(&data[at as usize + 4..at as usize + 8], b"\xA9xyz" | b"udta" | b"meta"));
+        }
+        let private = mp4_boxes_to_free(&mut Cursor::new(&data), StripPolicy::Private).unwrap();
+        assert_eq!(private, [xyz, meta]);
+        let all = mp4_boxes_to_free(&mut Cursor::new(&data), StripPolicy::All).unwrap();
+        assert_eq!(all, [udta, meta, track_udta]);
+
+        // Nothing to do without a location, or in a file cut short
+        let plain = [mp4_box(b"ftyp", b"isom"), mp4_box(b"moov", &mp4_box(b"mvhd", &[0; 100]))].concat();
+        assert!(mp4_boxes_to_free(&mut Cursor::new(&plain), StripPolicy::Private).unwrap().is_empty());
+        assert!(mp4_boxes_to_free(&mut Cursor::new(&data[..xyz as usize]), StripPolicy::All).unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn mp4_copies_have_the_location_freed() {
+        let (data, [xyz, _, meta, _]) = mp4();
+        let dir = std::env::temp_dir().join(format!("rusty-gallery-{}-strip-mp4", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let source = dir.join("clip.mp4");
+        std::fs::write(&source, &data).unwrap();
+
+        let copy = Stripper::new(&dir, StripPolicy::Private).get(&source, Format::Mp4).await.unwrap();
+        assert_ne!(copy, source);
+        let stripped = std::fs::read(&copy).unwrap();
+        assert_eq!(stripped.len(), data.len());
+        for at in [xyz, meta] {
+            assert_eq!(&stripped[at as usize + 4..at as usize + 8], b"free");
+        }
+        assert!(!find(&stripped, b"\xA9xyz"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
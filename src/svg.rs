@@ -0,0 +1,276 @@
+use quick_xml::{
+    events::{attributes::Attribute, BytesStart, Event},
+    Reader, Writer,
+};
+
+/// Elements that run code, embed other documents or load other resources
+const BLOCKED_ELEMENTS: [&str; 10] = [
+    "script",
+    "foreignobject",
+    "iframe",
+    "embed",
+    "object",
+    "handler",
+    "listener",
+    "audio",
+    "video",
+    "discard",
+];
+
+/// Animation elements, which could change a link into a `javascript:` one
+const ANIMATIONS: [&str; 5] = ["set", "animate", "animatecolor", "animatemotion", "animatetransform"];
+
+/// Pixel size of an SVG from its root element: `width` and `height` when
+/// they are plain numbers, else the `viewBox`. Also checks that the whole
+/// document is well-formed XML with an `<svg>` root.
+pub fn inspect(data: &[u8]) -> Result<Option<(u32, u32)>, String> {
+    let mut reader = Reader::from_reader(data);
+    let mut size = None;
+    let mut root = true;
+    loop {
+        match reader.read_event().map_err(|e| e.to_string())? {
+            Event::Eof => break,
+            Event::Start(e) | Event::Empty(e) if root => {
+                if !e.local_name().as_ref().eq_ignore_ascii_case(b"svg") {
+                    return Err(format!("root element is <{}>", String::from_utf8_lossy(e.name().as_ref())));
+                }
+                root = false;
+                size = root_size(&e);
+            }
+            _ => {}
+        }
+    }
+    if root {
+        return Err("no <svg> element".to_string());
+    }
+    Ok(size)
+}
+
+fn root_size(svg: &BytesStart) -> Option<(u32, u32)> {
+    let attr = |name: &str| {
+        svg.attributes()
+            .flatten()
+            .find(|a| a.key.local_name().as_ref() == name.as_bytes())
+            .and_then(|a| a.unescape_value().ok().map(|v| v.trim().to_string()))
+    };
+    let pixels = |value: Option<String>| {
+        let value = value?;
+        let n: f64 = value.strip_suffix("px").unwrap_or(&value).trim().parse().ok()?;
+        (n >= 1.0).then_some(n.round() as u32)
+    };
+    if let (Some(w), Some(h)) = (pixels(attr("width")), pixels(attr("height"))) {
+        return Some((w, h));
+    }
+    let view_box = attr("viewBox")?;
+    let numbers: Vec<f64> = view_box
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|s| !s.is_empty())
+        .map(str::parse)
+        .collect::<Result<_, _>>()
+        .ok()?;
+    match numbers[..] {
+        [_, _, w, h] if w >= 1.0 && h >= 1.0 => Some((w.round() as u32, h.round() as u32)),
+        _ => None,
+    }
+}
+
+/// `data` without scripts, event handlers, links to anything outside the
+/// document (in attributes and in `<style>` sheets alike) and the other ways
+/// an SVG opened on the gallery's origin could run code or phone home. With
+/// `drop_metadata` the `<metadata>` element and comments go too. Nothing is
+/// copied past a parse error, so broken input gives a shorter document rather
+/// than unchecked markup.
+pub fn sanitize(data: &[u8], drop_metadata: bool) -> Vec<u8> {
+    let mut reader = Reader::from_reader(data);
+    let mut writer = Writer::new(Vec::with_capacity(data.len()));
+    // Depth inside an element that is left out with everything in it
+    let mut skipping = 0usize;
+    let mut in_style = false;
+    while let Ok(event) = reader.read_event() {
+        let written = match event {
+            Event::Eof => break,
+            Event::Start(e) => {
+                if skipping > 0 || !allowed_element(&e, drop_metadata) {
+                    skipping += 1;
+                    continue;
+                }
+                in_style = e.local_name().as_ref().eq_ignore_ascii_case(b"style");
+                match clean_attributes(&e) {
+                    Some(e) => writer.write_event(Event::Start(e)),
+                    None => break,
+                }
+            }
+            Event::End(e) => {
+                if skipping > 0 {
+                    skipping -= 1;
+                    continue;
+                }
+                in_style = false;
+                writer.write_event(Event::End(e))
+            }
+            Event::Empty(e) => {
+                if skipping > 0 || !allowed_element(&e, drop_metadata) {
+                    continue;
+                }
+                match clean_attributes(&e) {
+                    Some(e) => writer.write_event(Event::Empty(e)),
+                    None => break,
+                }
+            }
+            Event::Comment(_) if drop_metadata => continue,
+            // A style sheet that loads anything is left out whole
+            Event::Text(_) | Event::CData(_) if in_style && !safe_style(&event) => continue,
+            Event::Decl(_) | Event::Text(_) | Event::CData(_) | Event::Comment(_) if skipping == 0 => {
+                writer.write_event(event)
+            }
+            // Doctypes can define entities, processing instructions can pull
+            // in style sheets
+            _ => continue,
+        };
+        if written.is_err() {
+            break;
+        }
+    }
+    writer.into_inner()
+}
+
+fn allowed_element(e: &BytesStart, drop_metadata: bool) -> bool {
+    let name = String::from_utf8_lossy(e.local_name().as_ref()).to_ascii_lowercase();
+    if BLOCKED_ELEMENTS.contains(&name.as_str()) || (drop_metadata && name == "metadata") {
+        return false;
+    }
+    if ANIMATIONS.contains(&name.as_str()) {
+        let target = e
+            .attributes()
+            .flatten()
+            .find(|a| a.key.local_name().as_ref() == b"attributeName")
+            .map(|a| String::from_utf8_lossy(&a.value).to_ascii_lowercase());
+        if target.is_some_and(|t| t.contains("href") || t.trim_start().starts_with("on")) {
+            return false;
+        }
+    }
+    true
+}
+
+/// `e` with only safe attributes, or `None` if they can't be parsed.
+fn clean_attributes<'a>(e: &BytesStart<'a>) -> Option<BytesStart<'a>> {
+    let mut clean = BytesStart::new(String::from_utf8_lossy(e.name().as_ref()).into_owned());
+    for attr in e.attributes() {
+        let attr = attr.ok()?;
+        if safe_attribute(&attr) {
+            clean.push_attribute(attr);
+        }
+    }
+    Some(clean)
+}
+
+fn safe_attribute(attr: &Attribute) -> bool {
+    let name = String::from_utf8_lossy(attr.key.local_name().as_ref()).to_ascii_lowercase();
+    if name.starts_with("on") {
+        return false;
+    }
+    // Entities are resolved first, as the browser would
+    let Ok(value) = attr.unescape_value() else { return false };
+    if name == "href" || name == "src" {
+        // Only parts of this document and embedded bitmaps
+        let value = normalize(&value);
+        return value.starts_with('#')
+            || ["data:image/png", "data:image/jpeg", "data:image/gif", "data:image/webp"]
+                .iter()
+                .any(|p| value.starts_with(p));
+    }
+    local_css(&value)
+}
+
+/// Whether the text of a `<style>` element keeps to the document.
+fn safe_style(event: &Event) -> bool {
+    match event {
+        Event::Text(t) => t.unescape().is_ok_and(|t| local_css(&t)),
+        Event::CData(c) => local_css(&String::from_utf8_lossy(c)),
+        _ => true,
+    }
+}
+
+/// Whether an attribute value or style sheet loads nothing from outside the
+/// document: no script URLs, no `@import`, `url()` only for `#id`, and no
+/// CSS escapes, which could spell any of those in another way.
+fn local_css(value: &str) -> bool {
+    let value = normalize(value);
+    if ["javascript:", "vbscript:", "@import", "\\"].iter().any(|p| value.contains(p)) {
+        return false;
+    }
+    value
+        .match_indices("url(")
+        .all(|(i, _)| value[i + 4..].trim_start_matches(['"', '\'']).starts_with('#'))
+}
+
+/// `value` in lowercase without the spaces and control characters browsers
+/// skip over in URLs.
+fn normalize(value: &str) -> String {
+    value
+        .chars()
+        .filter(|c| !c.is_whitespace() && !c.is_control())
+        .collect::<String>()
+        .to_ascii_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn clean(svg: &str) -> String {
+        String::from_utf8(sanitize(svg.as_bytes(), false)).unwrap()
+    }
+
+    #[test]
+    fn event_handlers_go() {
+        let out = clean(r#"<svg onload="alert(1)" width="10"><rect ONCLICK="alert(2)" fill="red"/></svg>"#);
+        assert!(!out.to_lowercase().contains("alert"), "{out}");
+        assert!(out.contains(r#"width="10""#) && out.contains(r#"fill="red""#), "{out}");
+    }
+
+    #[test]
+    fn script_links_with_encoded_whitespace_go() {
+        let out = clean(r#"<svg><a href="jav&#x09;ascript:alert(1)"><text>x</text></a><a href="java&#10;script:alert(2)"/></svg>"#);
+        assert!(!out.contains("alert"), "{out}");
+        assert!(out.contains("<text>x</text>"), "{out}");
+    }
+
+    #[test]
+    fn animations_of_links_go() {
+        let out = clean(r##"<svg><a href="#x"><animate attributeName="href" to="javascript:alert(1)"/><set attributeName=" onclick" to="x"/><animate attributeName="x" to="5"/></a></svg>"##);
+        assert!(!out.contains("alert") && !out.contains("onclick"), "{out}");
+        assert!(out.contains(r#"attributeName="x""#), "{out}");
+    }
+
+    #[test]
+    fn style_sheets_that_load_anything_go() {
+        for sheet in [
+            "@import url(https://evil.example/x.css);",
+            "rect{fill:url(https://evil.example/t.png)}",
+            "@import 'https://evil.example/x.css';",
+            r"rect{fill:u\72l(https://evil.example/t.png)}",
+            "<![CDATA[rect{fill:url( 'https://evil.example/t.png')}]]>",
+        ] {
+            let out = clean(&format!("<svg><style>{sheet}</style><rect/></svg>"));
+            assert!(!out.contains("evil"), "{out}");
+            assert!(out.contains("<style></style>") && out.contains("<rect/>"), "{out}");
+        }
+        let out = clean(r#"<svg><rect style="fill:url(https://evil.example/t.png)"/></svg>"#);
+        assert!(!out.contains("evil"), "{out}");
+    }
+
+    #[test]
+    fn local_styles_stay() {
+        let out = clean(r##"<svg><style>rect{fill:url(#grad)} .a &gt; b{stroke:red}</style><rect style="fill:url('#grad')"/></svg>"##);
+        assert!(out.contains("fill:url(#grad)") && out.contains("stroke:red"), "{out}");
+        assert!(out.contains(r#"style="fill:url('#grad')""#), "{out}");
+    }
+
+    #[test]
+    fn size_from_attributes_or_view_box() {
+        assert_eq!(inspect(br#"<svg width="640px" height="480"/>"#), Ok(Some((640, 480))));
+        assert_eq!(inspect(br#"<svg viewBox="0 0 100.4 50"/>"#), Ok(Some((100, 50))));
+        assert!(inspect(b"<html><svg/></html>").is_err());
+    }
+}
@@ -0,0 +1,384 @@
+use serde::Deserialize;
+use std::{fmt, path::Path};
+
+/// Keyword → tag mapping loaded from a TOML or JSON file at startup.
+///
+/// Every rule that matches a file name contributes its tag, ordered from
+/// highest to lowest `priority` (rules with the same priority keep the order
+/// they have in the file). Files that match nothing get the fallback tag.
+pub struct TagRules {
+    fallback: String,
+    rules: Vec<TagRule>,
+}
+
+struct TagRule {
+    tag: String,
+    matchers: Vec<Matcher>,
+}
+
+enum Matcher {
+    Keyword(String),
+    Glob(glob::Pattern),
+    Regex(regex::Regex),
+}
+
+/// Tag rules as written in a rules file or inline in the config file.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RulesSpec {
+    #[serde(default = "default_fallback")]
+    fallback: String,
+    #[serde(default, rename = "rule")]
+    rules: Vec<RuleSpec>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RuleSpec {
+    tag: String,
+    #[serde(default)]
+    keywords: Vec<String>,
+    #[serde(default)]
+    globs: Vec<String>,
+    #[serde(default)]
+    regex: Vec<String>,
+    #[serde(default)]
+    priority: i32,
+}
+
+#[derive(Debug)]
+pub enum TagRulesError {
+    Io(std::io::Error),
+    Parse(String),
+    Rule { tag: String, reason: String },
+}
+
+impl fmt::Display for TagRulesError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TagRulesError::Io(e) => write!(f, "cannot read tag rules: {e}"),
+            TagRulesError::Parse(e) => write!(f, "invalid tag rules: {e}"),
+            TagRulesError::Rule { tag, reason } => write!(f, "invalid rule for tag {tag:?}: {reason}"),
+        }
+    }
+}
+
+impl std::error::Error for TagRulesError {}
+
+fn default_fallback() -> String {
+    "Various".to_string()
+}
+
+impl TagRules {
+    /// Reads rules from `path`. Files ending in `.json` are parsed as JSON,
+    /// everything else as TOML.
+    pub fn load(path: &Path) -> Result<Self, TagRulesError> {
+        let text = std::fs::read_to_string(path).map_err(TagRulesError::Io)?;
+        let file: RulesSpec = if path.extension().is_some_and(|e| e.eq_ignore_ascii_case("json")) {
+            serde_json::from_str(&text).map_err(|e| TagRulesError::Parse(e.to_string()))?
+        } else {
+            toml::from_str(&text).map_err(|e| TagRulesError::Parse(e.to_string()))?
+        };
+        Self::compile(file)
+    }
+
+    /// Rules that tag everything with the default fallback.
+    pub fn empty() -> Self {
+        TagRules {
+            fallback: default_fallback(),
+            rules: Vec::new(),
+        }
+    }
+
+    /// Checks and compiles the matchers of every rule.
+    pub fn compile(file: RulesSpec) -> Result<Self, TagRulesError> {
+        let mut specs = file.rules;
+        // Stable sort, so equal priorities keep their file order
+        specs.sort_by_key(|r| std::cmp::Reverse(r.priority));
+
+        let mut rules = Vec::with_capacity(specs.len());
+        for spec in specs {
+            let invalid = |reason: String| TagRulesError::Rule {
+                tag: spec.tag.clone(),
+                reason,
+            };
+
+            let mut matchers = Vec::new();
+            for kw in &spec.keywords {
+                matchers.push(Matcher::Keyword(kw.to_ascii_lowercase()));
+            }
+            for g in &spec.globs {
+                let pattern = glob::Pattern::new(g).map_err(|e| invalid(e.to_string()))?;
+                matchers.push(Matcher::Glob(pattern));
+            }
+            for r in &spec.regex {
+                let re = regex::RegexBuilder::new(r)
+                    .case_insensitive(true)
+                    .build()
+                    .map_err(|e| invalid(e.to_string()))?;
+                matchers.push(Matcher::Regex(re));
+            }
+            if matchers.is_empty() {
+                return Err(invalid("needs at least one keyword, glob or regex".into()));
+            }
+
+            rules.push(TagRule {
+                tag: spec.tag,
+                matchers,
+            });
+        }
+
+        Ok(TagRules {
+            fallback: file.fallback,
+            rules,
+        })
+    }
+
+    /// Tag given to files that match no rule.
+    pub fn fallback(&self) -> &str {
+        &self.fallback
+    }
+
+    /// Tags for a file, by its path in the gallery folder, without
+    /// duplicates; matching is case-insensitive.
+    pub fn tags_for(&self, name: &str) -> Vec<&str> {
+        let lower = name.to_ascii_lowercase();
+        let mut tags: Vec<&str> = Vec::new();
+        for rule in &self.rules {
+            if !tags.contains(&rule.tag.as_str()) && rule.matchers.iter().any(|m| m.matches(&lower)) {
+                tags.push(&rule.tag);
+            }
+        }
+        if tags.is_empty() {
+            tags.push(&self.fallback);
+        }
+        tags
+    }
+}
+
+/// Tags given automatically from an image's pixel size, in the order the
+/// filter bar lists them.
+pub const SIZE_TAGS: [&str; 6] = ["4K", "1440p", "1080p", "Ultrawide", "Square", "Portrait"];
+
+/// Size tags for a `width` x `height` image: the highest resolution class it
+/// reaches (measured on its long and short side, so phone shots count too)
+/// plus its shape.
+pub fn size_tags(width: u32, height: u32) -> Vec<&'static str> {
+    let (long, short) = (width.max(height), width.min(height));
+    let mut tags = Vec::new();
+    if long >= 3840 && short >= 2160 {
+        tags.push("4K");
+    } else if long >= 2560 && short >= 1440 {
+        tags.push("1440p");
+    } else if long >= 1920 && short >= 1080 {
+        tags.push("1080p");
+    }
+
+    // 21:9 and 32:9 are both well above 2:1
+    let ratio = f64::from(width) / f64::from(height.max(1));
+    if ratio >= 2.1 {
+        tags.push("Ultrawide");
+    } else if (0.9..=1.1).contains(&ratio) {
+        tags.push("Square");
+    } else if ratio < 0.9 {
+        tags.push("Portrait");
+    }
+    tags
+}
+
+/// How a set of selected tags is combined when filtering.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MatchMode {
+    /// The image carries at least one of the selected tags.
+    #[default]
+    Any,
+    /// The image carries every selected tag.
+    All,
+}
+
+impl MatchMode {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            MatchMode::Any => "any",
+            MatchMode::All => "all",
+        }
+    }
+
+    /// Whether an image with `tags` passes a filter on `selected`. An empty
+    /// selection lets everything through.
+    pub fn matches<T: AsRef<str>, S: AsRef<str>>(self, tags: &[T], selected: &[S]) -> bool {
+        let has = |s: &S| tags.iter().any(|t| t.as_ref() == s.as_ref());
+        selected.is_empty()
+            || match self {
+                MatchMode::Any => selected.iter().any(has),
+                MatchMode::All => selected.iter().all(has),
+            }
+    }
+}
+
+impl Matcher {
+    fn matches(&self, lower_name: &str) -> bool {
+        const GLOB_OPTS: glob::MatchOptions = glob::MatchOptions {
+            case_sensitive: false,
+            require_literal_separator: false,
+            require_literal_leading_dot: false,
+        };
+        match self {
+            Matcher::Keyword(kw) => lower_name.contains(kw.as_str()),
+            // The file name alone, so patterns work in albums too, or the
+            // whole path, for patterns about the folder
+            Matcher::Glob(p) => {
+                let file_name = lower_name.rsplit('/').next().unwrap_or(lower_name);
+                p.matches_with(file_name, GLOB_OPTS) || p.matches_with(lower_name, GLOB_OPTS)
+            }
+            Matcher::Regex(re) => re.is_match(lower_name),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules(toml: &str) -> TagRules {
+        TagRules::compile(toml::from_str(toml).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn every_matching_rule_tags_by_priority() {
+        let rules = rules(
+            r#"
+            [[rule]]
+            tag = "Love Live"
+            keywords = ["lovelive"]
+
+            [[rule]]
+            tag = "Madoka Magica"
+            keywords = ["madoka"]
+            priority = 10
+
+            [[rule]]
+            tag = "Crossover"
+            keywords = ["crossover"]
+            "#,
+        );
+        assert_eq!(
+            rules.tags_for("lovelive_madoka_crossover.png"),
+            ["Madoka Magica", "Love Live", "Crossover"]
+        );
+    }
+
+    #[test]
+    fn equal_priorities_keep_the_file_order() {
+        let rules = rules(
+            r#"
+            [[rule]]
+            tag = "B"
+            keywords = ["b"]
+            priority = 1
+
+            [[rule]]
+            tag = "A"
+            keywords = ["a"]
+            priority = 1
+            "#,
+        );
+        assert_eq!(rules.tags_for("ab.png"), ["B", "A"]);
+    }
+
+    #[test]
+    fn a_tag_is_given_once() {
+        let rules = rules(
+            r#"
+            [[rule]]
+            tag = "Rin"
+            keywords = ["rin"]
+
+            [[rule]]
+            tag = "Rin"
+            regex = ["hoshizora"]
+            "#,
+        );
+        assert_eq!(rules.tags_for("rin_hoshizora.png"), ["Rin"]);
+    }
+
+    #[test]
+    fn fallback_only_when_nothing_matches() {
+        let rules = rules(
+            r#"
+            fallback = "Misc"
+
+            [[rule]]
+            tag = "Bocchi The Rock"
+            keywords = ["bocchi"]
+            "#,
+        );
+        assert_eq!(rules.tags_for("bocchi.png"), ["Bocchi The Rock"]);
+        assert_eq!(rules.tags_for("sunset.png"), ["Misc"]);
+        assert_eq!(TagRules::empty().tags_for("sunset.png"), ["Various"]);
+    }
+
+    #[test]
+    fn matching_ignores_case() {
+        let rules = rules(
+            r#"
+            [[rule]]
+            tag = "Keyword"
+            keywords = ["BOCCHI"]
+
+            [[rule]]
+            tag = "Glob"
+            globs = ["Kessoku_*.PNG"]
+
+            [[rule]]
+            tag = "Regex"
+            regex = ["^NICO\\d+"]
+            "#,
+        );
+        assert_eq!(rules.tags_for("Bocchi.png"), ["Keyword"]);
+        assert_eq!(rules.tags_for("KESSOKU_band.png"), ["Glob"]);
+        assert_eq!(rules.tags_for("nico42.jpg"), ["Regex"]);
+        // Globs match the whole name
+        assert_eq!(rules.tags_for("not_kessoku_band.png"), ["Various"]);
+    }
+
+    #[test]
+    fn globs_work_in_albums() {
+        let rules = rules(
+            r#"
+            [[rule]]
+            tag = "Bocchi The Rock"
+            globs = ["bocchi_*.png"]
+
+            [[rule]]
+            tag = "Kessoku Band"
+            globs = ["kessoku/*"]
+            "#,
+        );
+        assert_eq!(rules.tags_for("bocchi_1.png"), ["Bocchi The Rock"]);
+        assert_eq!(rules.tags_for("anime/bocchi_1.png"), ["Bocchi The Rock"]);
+        assert_eq!(rules.tags_for("Kessoku/live.jpg"), ["Kessoku Band"]);
+        assert_eq!(rules.tags_for("anime/kessoku.jpg"), ["Various"]);
+    }
+
+    #[test]
+    fn invalid_rules_are_rejected() {
+        let compile = |toml: &str| TagRules::compile(toml::from_str(toml).unwrap());
+        assert!(matches!(compile("[[rule]]\ntag = \"X\""), Err(TagRulesError::Rule { .. })));
+        assert!(matches!(compile("[[rule]]\ntag = \"X\"\nregex = [\"(\"]"), Err(TagRulesError::Rule { .. })));
+        assert!(matches!(compile("[[rule]]\ntag = \"X\"\nglobs = [\"[\"]"), Err(TagRulesError::Rule { .. })));
+    }
+
+    #[test]
+    fn match_modes() {
+        let tags = ["Rin", "4K"];
+        assert!(MatchMode::Any.matches(&tags, &["Nico", "Rin"]));
+        assert!(!MatchMode::Any.matches(&tags, &["Nico"]));
+        assert!(MatchMode::All.matches(&tags, &["Rin", "4K"]));
+        assert!(!MatchMode::All.matches(&tags, &["Rin", "Nico"]));
+        let nothing: [&str; 0] = [];
+        assert!(MatchMode::Any.matches(&tags, &nothing));
+        assert!(MatchMode::All.matches(&tags, &nothing));
+    }
+}
@@ -0,0 +1,671 @@
+use crate::{avif, sniff::Format};
+use image::{codecs::{avif::AvifEncoder, jpeg::JpegEncoder}, imageops::FilterType, DynamicImage, ImageFormat, ImageReader};
+use jxl_oxide::integration::JxlDecoder;
+use serde::Deserialize;
+use std::{
+    fmt,
+    fs::File,
+    hash::{DefaultHasher, Hash, Hasher},
+    io::{BufReader, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+    process::{Command, Output, Stdio},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+const JPEG_QUALITY: u8 = 82;
+const WEBP_QUALITY: f32 = 80.0;
+const AVIF_QUALITY: u8 = 70;
+/// 1 (slowest, smallest) to 10; anything lower makes the first view of a page crawl
+const AVIF_SPEED: u8 = 8;
+/// Most pixels `/render` produces, whatever the sides; a bit more than 8K UHD
+const MAX_RENDER_PIXELS: u64 = 40_000_000;
+/// Longest ffmpeg gets for a poster frame before it is killed, for files
+/// that make it hang
+const FFMPEG_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Downscaled and resized copies of wallpapers, generated on first request
+/// and kept on disk. Videos are represented by a poster frame.
+pub struct ThumbCache {
+    dir: PathBuf,
+    renders: PathBuf,
+    sizes: Vec<u32>,
+    /// ffmpeg program that takes poster frames out of videos
+    ffmpeg: Arc<Path>,
+    /// Most bytes kept in `renders`, which any request can add to; the least
+    /// recently used files go first
+    render_limit: u64,
+    /// Set while `renders` is being trimmed, so only one request does it
+    trimming: Arc<AtomicBool>,
+}
+
+#[derive(Debug)]
+pub enum ThumbError {
+    /// The requested width is not one of the configured sizes
+    Size(u32),
+    Io(std::io::Error),
+    Image(image::ImageError),
+    /// ffmpeg is missing or gave no frame
+    Video(String),
+}
+
+impl fmt::Display for ThumbError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ThumbError::Size(size) => write!(f, "unsupported thumbnail size {size}"),
+            ThumbError::Io(e) => write!(f, "{e}"),
+            ThumbError::Image(e) => write!(f, "cannot make thumbnail: {e}"),
+            ThumbError::Video(e) => write!(f, "cannot take a frame out of the video: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for ThumbError {}
+
+impl From<std::io::Error> for ThumbError {
+    fn from(e: std::io::Error) -> Self {
+        ThumbError::Io(e)
+    }
+}
+
+impl From<image::ImageError> for ThumbError {
+    fn from(e: image::ImageError) -> Self {
+        ThumbError::Image(e)
+    }
+}
+
+impl ThumbCache {
+    /// Thumbnails go to `cache_dir/thumbs`, rendered images to
+    /// `cache_dir/renders`, which is kept under `render_limit` bytes.
+    pub fn new(cache_dir: &Path, mut sizes: Vec<u32>, ffmpeg: Arc<Path>, render_limit: u64) -> Self {
+        sizes.sort_unstable();
+        sizes.dedup();
+        ThumbCache {
+            dir: cache_dir.join("thumbs"),
+            renders: cache_dir.join("renders"),
+            sizes,
+            ffmpeg,
+            render_limit,
+            trimming: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// Thumbnail widths, smallest first.
+    pub fn sizes(&self) -> &[u32] {
+        &self.sizes
+    }
+
+    /// Size to use for grid cards: the smallest one at least `min` wide.
+    pub fn size_for(&self, min: u32) -> u32 {
+        self.sizes
+            .iter()
+            .copied()
+            .find(|&s| s >= min)
+            .or_else(|| self.sizes.last().copied())
+            .unwrap_or(min)
+    }
+
+    /// Path of a cached thumbnail of `source`, `width` pixels wide at most,
+    /// creating it first if needed. `format` overrides the JPEG/PNG default.
+    pub async fn get(&self, source: &Path, width: u32, format: Option<OutputFormat>) -> Result<PathBuf, ThumbError> {
+        if !self.sizes.contains(&width) {
+            return Err(ThumbError::Size(width));
+        }
+        let thumb = cached(&self.dir, source, width, format, &self.ffmpeg, move |img| {
+            if img.width() > width {
+                img.thumbnail(width, u32::MAX)
+            } else {
+                img
+            }
+        });
+        Ok(thumb.await?.0)
+    }
+
+    /// Path of a cached copy of `source` resized as `spec` says, creating it
+    /// first if needed. `fallback` is the format to use when the spec names
+    /// none.
+    pub async fn render(
+        &self,
+        source: &Path,
+        spec: RenderSpec,
+        fallback: Option<OutputFormat>,
+    ) -> Result<PathBuf, ThumbError> {
+        let format = spec.format.or(fallback);
+        let rendered = cached(&self.renders, source, (spec, format), format, &self.ffmpeg, move |img| spec.apply(img));
+        self.used_render(rendered.await?).await
+    }
+
+    /// Path of a cached full size copy of `source` in a format browsers can
+    /// show, for formats they can't (TIFF, BMP), creating it first if needed.
+    pub async fn preview(&self, source: &Path, format: Option<OutputFormat>) -> Result<PathBuf, ThumbError> {
+        let preview = cached(&self.renders, source, ("preview", format), format, &self.ffmpeg, |img| img);
+        self.used_render(preview.await?).await
+    }
+
+    /// Marks a file in `renders` as just used, and trims the folder after a
+    /// new file was added to it.
+    async fn used_render(&self, (path, created): (PathBuf, bool)) -> Result<PathBuf, ThumbError> {
+        if !created {
+            // The mtime stands in for the last use; atime is often not kept
+            let touched = path.clone();
+            let _ = tokio::task::spawn_blocking(move || {
+                std::fs::File::options().write(true).open(touched)?.set_modified(SystemTime::now())
+            })
+            .await;
+        } else if !self.trimming.swap(true, Ordering::AcqRel) {
+            let (dir, limit, trimming) = (self.renders.clone(), self.render_limit, self.trimming.clone());
+            tokio::task::spawn_blocking(move || {
+                if let Err(e) = trim(&dir, limit) {
+                    println!("Cannot trim {}: {e}", dir.display());
+                }
+                trimming.store(false, Ordering::Release);
+            });
+        }
+        Ok(path)
+    }
+}
+
+/// How `/render` fits an image into the requested box.
+#[derive(Clone, Copy, Hash, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RenderMode {
+    /// Scale down or up to fill the box, cutting off what sticks out
+    #[default]
+    Fill,
+    /// Scale to fit inside the box, keeping the whole image
+    Fit,
+    /// Cut the box out of the full size image, without scaling
+    Crop,
+}
+
+/// Output format of generated images.
+#[derive(Clone, Copy, Hash, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    #[serde(alias = "jpeg")]
+    Jpg,
+    Png,
+    Webp,
+    Avif,
+}
+
+impl OutputFormat {
+    fn extension(self) -> &'static str {
+        match self {
+            OutputFormat::Jpg => "jpg",
+            OutputFormat::Png => "png",
+            OutputFormat::Webp => "webp",
+            OutputFormat::Avif => "avif",
+        }
+    }
+
+    /// The most compact format an `Accept` header allows, or `None` to stay
+    /// with JPEG/PNG.
+    pub fn negotiate(accept: &str) -> Option<Self> {
+        let accepted = |mime: &str| {
+            accept.split(',').any(|item| {
+                let mut parts = item.split(';').map(str::trim);
+                let matches = parts.next().is_some_and(|m| m.eq_ignore_ascii_case(mime));
+                let quality = parts
+                    .find_map(|p| p.strip_prefix("q="))
+                    .and_then(|q| q.parse::<f32>().ok())
+                    .unwrap_or(1.0);
+                matches && quality > 0.0
+            })
+        };
+        if accepted("image/avif") {
+            Some(OutputFormat::Avif)
+        } else if accepted("image/webp") {
+            Some(OutputFormat::Webp)
+        } else {
+            None
+        }
+    }
+}
+
+/// A checked `/render` request. Missing sides follow the image's aspect
+/// ratio, and the focus point (fractions of the width and height) decides
+/// which part survives a crop.
+#[derive(Clone, Copy)]
+pub struct RenderSpec {
+    width: Option<u32>,
+    height: Option<u32>,
+    mode: RenderMode,
+    focus: (f32, f32),
+    /// Explicitly asked for with `?format=`
+    pub format: Option<OutputFormat>,
+    /// Longest side allowed, also for sides that follow the image's shape
+    max_side: u32,
+}
+
+// The focus is hashed by its bits; every spec is already validated
+impl Hash for RenderSpec {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        (self.width, self.height, self.mode, self.focus.0.to_bits(), self.focus.1.to_bits(), self.format).hash(state);
+    }
+}
+
+impl RenderSpec {
+    /// Checks the requested size against `max_side` and the pixel cap, so a
+    /// request can't make the server build arbitrarily large images.
+    pub fn new(
+        width: Option<u32>,
+        height: Option<u32>,
+        mode: RenderMode,
+        focus: (f32, f32),
+        format: Option<OutputFormat>,
+        max_side: u32,
+    ) -> Result<Self, String> {
+        if width.is_none() && height.is_none() {
+            return Err("set w, h or both".to_string());
+        }
+        for side in [width, height].into_iter().flatten() {
+            if side == 0 || side > max_side {
+                return Err(format!("w and h must be between 1 and {max_side}"));
+            }
+        }
+        if let (Some(w), Some(h)) = (width, height) {
+            if u64::from(w) * u64::from(h) > MAX_RENDER_PIXELS {
+                return Err(format!("w × h must be at most {MAX_RENDER_PIXELS} pixels"));
+            }
+        }
+        if !(0.0..=1.0).contains(&focus.0) || !(0.0..=1.0).contains(&focus.1) {
+            return Err("fx and fy must be between 0 and 1".to_string());
+        }
+        // Finer steps make no visible difference, only more cached files
+        let focus = ((focus.0 * 100.0).round() / 100.0, (focus.1 * 100.0).round() / 100.0);
+        Ok(RenderSpec {
+            width,
+            height,
+            mode,
+            focus,
+            format,
+            max_side,
+        })
+    }
+
+    fn apply(&self, img: DynamicImage) -> DynamicImage {
+        let (iw, ih) = (img.width(), img.height());
+        // A missing side follows the aspect ratio of the image
+        let (w, h) = match (self.width, self.height) {
+            (Some(w), Some(h)) => (w, h),
+            (Some(w), None) => (w, scale(ih, w, iw)),
+            (None, Some(h)) => (scale(iw, h, ih), h),
+            (None, None) => (iw, ih),
+        };
+        // A side that follows a very thin image can be far too long
+        let shrink = (f64::from(w) * f64::from(h) / MAX_RENDER_PIXELS as f64)
+            .sqrt()
+            .max(f64::from(w.max(h)) / f64::from(self.max_side));
+        let (w, h) = if shrink > 1.0 {
+            let side = |s: u32| ((f64::from(s) / shrink).floor() as u32).max(1);
+            (side(w), side(h))
+        } else {
+            (w, h)
+        };
+        match self.mode {
+            RenderMode::Fit => img.resize(w, h, FilterType::Lanczos3),
+            RenderMode::Fill => {
+                // Cut the box's shape around the focus, then scale only
+                // that; scaling the whole image to cover a thin box first
+                // could take gigabytes
+                let (cw, ch) = fill_crop(iw, ih, w, h);
+                self.crop(img, cw, ch).resize_exact(w, h, FilterType::Lanczos3)
+            }
+            RenderMode::Crop => self.crop(img, w.min(iw), h.min(ih)),
+        }
+    }
+
+    /// Cuts a `w` x `h` box out of `img`, centred on the focus point as far
+    /// as the edges allow.
+    fn crop(&self, img: DynamicImage, w: u32, h: u32) -> DynamicImage {
+        let offset = |full: u32, part: u32, focus: f32| {
+            let centre = (f64::from(full) * f64::from(focus)).round() as i64;
+            (centre - i64::from(part) / 2).clamp(0, i64::from(full - part)) as u32
+        };
+        let x = offset(img.width(), w, self.focus.0);
+        let y = offset(img.height(), h, self.focus.1);
+        img.crop_imm(x, y, w, h)
+    }
+}
+
+/// Sides of the biggest part of an `iw` x `ih` image with the shape of a
+/// `w` x `h` box.
+fn fill_crop(iw: u32, ih: u32, w: u32, h: u32) -> (u32, u32) {
+    if u64::from(iw) * u64::from(h) > u64::from(ih) * u64::from(w) {
+        (scale(ih, w, h).min(iw), ih)
+    } else {
+        (iw, scale(iw, h, w).min(ih))
+    }
+}
+
+/// `value * num / den`, rounded and at least 1.
+fn scale(value: u32, num: u32, den: u32) -> u32 {
+    ((u64::from(value) * u64::from(num) + u64::from(den) / 2) / u64::from(den)).max(1) as u32
+}
+
+/// Name for a cached copy of `source`, from its path, size and mtime and
+/// `variant`, so editing or replacing a wallpaper produces a fresh copy.
+pub async fn cache_key(source: &Path, variant: impl Hash) -> std::io::Result<String> {
+    let meta = tokio::fs::metadata(source).await?;
+    let mtime = meta
+        .modified()?
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or_default();
+    let mut hasher = DefaultHasher::new();
+    (source, meta.len(), mtime, variant).hash(&mut hasher);
+    Ok(format!("{:016x}", hasher.finish()))
+}
+
+/// Deletes the least recently used files in `dir` once it holds more than
+/// `limit` bytes, down to 90% of it so that not every new file trims again.
+fn trim(dir: &Path, limit: u64) -> std::io::Result<()> {
+    let mut files = Vec::new();
+    let mut total = 0;
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let meta = entry.metadata()?;
+        // Files still being written are left to their request
+        if !meta.is_file() || entry.path().extension().is_some_and(|e| e == "tmp") {
+            continue;
+        }
+        total += meta.len();
+        files.push((meta.modified()?, meta.len(), entry.path()));
+    }
+    if total <= limit {
+        return Ok(());
+    }
+    files.sort();
+    for (_, len, path) in files {
+        if total <= limit / 10 * 9 {
+            break;
+        }
+        // Requests already serving the file keep their open handle
+        if std::fs::remove_file(&path).is_ok() {
+            total -= len;
+        }
+    }
+    Ok(())
+}
+
+/// Looks up the cached result of `transform` on `source`, or makes it. Also
+/// says whether the file was made just now.
+/// Without a `format`, images with transparency become PNG and the rest JPEG.
+async fn cached<V, F>(
+    dir: &Path,
+    source: &Path,
+    variant: V,
+    format: Option<OutputFormat>,
+    ffmpeg: &Arc<Path>,
+    transform: F,
+) -> Result<(PathBuf, bool), ThumbError>
+where
+    V: Hash,
+    F: FnOnce(DynamicImage) -> DynamicImage + Send + 'static,
+{
+    let key = cache_key(source, variant).await?;
+    let candidates = match format {
+        Some(f) => vec![f],
+        None => vec![OutputFormat::Jpg, OutputFormat::Png],
+    };
+    for f in candidates {
+        let cached = dir.join(format!("{key}.{}", f.extension()));
+        if tokio::fs::try_exists(&cached).await? {
+            return Ok((cached, false));
+        }
+    }
+
+    tokio::fs::create_dir_all(dir).await?;
+    let source = source.to_path_buf();
+    let dir = dir.to_path_buf();
+    let ffmpeg = ffmpeg.clone();
+    let generated = tokio::task::spawn_blocking(move || generate(&source, &dir, &key, format, &ffmpeg, transform))
+        .await
+        .map_err(|e| ThumbError::Io(std::io::Error::other(e)))?;
+    Ok((generated?, true))
+}
+
+/// Decodes and transforms `source`, then writes it atomically into `dir`.
+fn generate(
+    source: &Path,
+    dir: &Path,
+    key: &str,
+    format: Option<OutputFormat>,
+    ffmpeg: &Path,
+    transform: impl FnOnce(DynamicImage) -> DynamicImage,
+) -> Result<PathBuf, ThumbError> {
+    let img = transform(decode(source, ffmpeg)?);
+
+    let format = format.unwrap_or(if img.color().has_alpha() {
+        OutputFormat::Png
+    } else {
+        OutputFormat::Jpg
+    });
+    let path = dir.join(format!("{key}.{}", format.extension()));
+    let tmp = dir.join(format!("{key}.{:08x}.tmp", rand::random::<u32>()));
+
+    let write = || -> Result<(), ThumbError> {
+        let mut file = BufWriter::new(std::fs::File::create(&tmp)?);
+        match format {
+            OutputFormat::Png => img.write_to(&mut file, ImageFormat::Png)?,
+            OutputFormat::Jpg => img
+                .to_rgb8()
+                .write_with_encoder(JpegEncoder::new_with_quality(&mut file, JPEG_QUALITY))?,
+            OutputFormat::Webp => {
+                let webp = if img.color().has_alpha() {
+                    let rgba = img.to_rgba8();
+                    webp::Encoder::from_rgba(&rgba, img.width(), img.height()).encode(WEBP_QUALITY)
+                } else {
+                    let rgb = img.to_rgb8();
+                    webp::Encoder::from_rgb(&rgb, img.width(), img.height()).encode(WEBP_QUALITY)
+                };
+                file.write_all(&webp)?;
+            }
+            OutputFormat::Avif => {
+                let encoder = AvifEncoder::new_with_speed_quality(&mut file, AVIF_SPEED, AVIF_QUALITY);
+                if img.color().has_alpha() {
+                    img.to_rgba8().write_with_encoder(encoder)?;
+                } else {
+                    img.to_rgb8().write_with_encoder(encoder)?;
+                }
+            }
+        }
+        file.flush()?;
+        Ok(())
+    };
+    if let Err(e) = write() {
+        let _ = std::fs::remove_file(&tmp);
+        return Err(e);
+    }
+    std::fs::rename(&tmp, &path)?;
+    Ok(path)
+}
+
+/// Decodes the image in `source`, or the poster frame of a video. Animated
+/// images give their first frame.
+pub fn decode(source: &Path, ffmpeg: &Path) -> Result<DynamicImage, ThumbError> {
+    let mut head = Vec::with_capacity(64);
+    File::open(source)?.take(64).read_to_end(&mut head)?;
+    match Format::sniff(&head) {
+        Some(format) if format.is_video() => poster_frame(source, ffmpeg),
+        Some(Format::Avif) => Ok(avif::decode(&std::fs::read(source)?)?),
+        Some(Format::Jxl) => {
+            let decoder = JxlDecoder::new(BufReader::new(File::open(source)?))?;
+            Ok(DynamicImage::from_decoder(decoder)?)
+        }
+        _ => Ok(ImageReader::open(source)?.with_guessed_format()?.decode()?),
+    }
+}
+
+/// The frame one second into a video, past any fade in, or the first frame
+/// of shorter ones.
+fn poster_frame(source: &Path, ffmpeg: &Path) -> Result<DynamicImage, ThumbError> {
+    let mut error = String::new();
+    for seek in ["1", "0"] {
+        let mut command = Command::new(ffmpeg);
+        command
+            .args(["-v", "error", "-nostdin", "-ss", seek, "-i"])
+            .arg(source)
+            .args(["-frames:v", "1", "-f", "image2pipe", "-c:v", "png", "-"]);
+        let output = output_within(&mut command, FFMPEG_TIMEOUT)
+            .map_err(|e| ThumbError::Video(format!("cannot run {}: {e}", ffmpeg.display())))?
+            .ok_or_else(|| ThumbError::Video(format!("ffmpeg took over {}s", FFMPEG_TIMEOUT.as_secs())))?;
+        if output.status.success() && !output.stdout.is_empty() {
+            return Ok(image::load_from_memory_with_format(&output.stdout, ImageFormat::Png)?);
+        }
+        error = String::from_utf8_lossy(&output.stderr).trim().to_string();
+    }
+    if error.is_empty() {
+        error = "no frames".to_string();
+    }
+    Err(ThumbError::Video(error))
+}
+
+/// Like `Command::output`, but the program is killed and `None` returned
+/// once it runs longer than `timeout`.
+fn output_within(command: &mut Command, timeout: Duration) -> std::io::Result<Option<Output>> {
+    let mut child = command.stdin(Stdio::null()).stdout(Stdio::piped()).stderr(Stdio::piped()).spawn()?;
+    // Read on their own threads, so the program never waits on a full pipe
+    let stdout = read_all(child.stdout.take());
+    let stderr = read_all(child.stderr.take());
+    let deadline = Instant::now() + timeout;
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if Instant::now() >= deadline {
+            let _ = child.kill();
+            child.wait()?;
+            return Ok(None);
+        }
+        thread::sleep(Duration::from_millis(20));
+    };
+    Ok(Some(Output {
+        status,
+        stdout: stdout.join().unwrap_or_default(),
+        stderr: stderr.join().unwrap_or_default(),
+    }))
+}
+
+fn read_all(pipe: Option<impl Read + Send + 'static>) -> JoinHandle<Vec<u8>> {
+    thread::spawn(move || {
+        let mut data = Vec::new();
+        if let Some(mut pipe) = pipe {
+            let _ = pipe.read_to_end(&mut data);
+        }
+        data
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{GenericImageView, RgbImage};
+
+    fn spec(width: Option<u32>, height: Option<u32>, mode: RenderMode) -> RenderSpec {
+        RenderSpec::new(width, height, mode, (0.5, 0.5), None, 7680).unwrap()
+    }
+
+    #[test]
+    fn fill_crops_before_scaling() {
+        // Only a 3 pixel wide strip of the image is scaled up for a thin box
+        assert_eq!(fill_crop(3440, 1440, 16, 7680), (3, 1440));
+        assert_eq!(fill_crop(3440, 1440, 1920, 1080), (2560, 1440));
+        assert_eq!(fill_crop(1440, 3440, 1920, 1080), (1440, 810));
+        assert_eq!(fill_crop(1920, 1080, 3840, 2160), (1920, 1080));
+        // Never wider or taller than the image, nor empty
+        assert_eq!(fill_crop(7680, 1, 1, 7680), (1, 1));
+        assert_eq!(fill_crop(1, 7680, 7680, 1), (1, 1));
+
+        let img = DynamicImage::ImageRgb8(RgbImage::new(3440, 1440));
+        let out = spec(Some(16), Some(7680), RenderMode::Fill).apply(img);
+        assert_eq!((out.width(), out.height()), (16, 7680));
+    }
+
+    #[test]
+    fn fill_keeps_the_focus() {
+        // Left half black, right half white
+        let img = RgbImage::from_fn(200, 100, |x, _| image::Rgb([if x < 100 { 0 } else { 255 }; 3]));
+        let spec = RenderSpec::new(Some(10), Some(10), RenderMode::Fill, (1.0, 0.5), None, 7680).unwrap();
+        let out = spec.apply(DynamicImage::ImageRgb8(img)).to_rgb8();
+        assert_eq!(out.get_pixel(5, 5).0, [255; 3]);
+    }
+
+    #[test]
+    fn trim_drops_least_recently_used() {
+        let dir = std::env::temp_dir().join(format!("rusty-gallery-{}-trim", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let now = SystemTime::now();
+        for (i, name) in ["old.jpg", "mid.jpg", "new.jpg", "busy.tmp"].iter().enumerate() {
+            let file = std::fs::File::create(dir.join(name)).unwrap();
+            file.set_len(100).unwrap();
+            file.set_modified(now - std::time::Duration::from_secs(100 - i as u64 * 10)).unwrap();
+        }
+        trim(&dir, 250).unwrap();
+        let mut left: Vec<String> = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().into_string().unwrap())
+            .collect();
+        left.sort();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(left, ["busy.tmp", "mid.jpg", "new.jpg"]);
+    }
+
+    #[test]
+    fn avif_and_jxl_are_decoded() {
+        let image = RgbImage::from_fn(40, 30, |x, y| image::Rgb([x as u8 * 6, y as u8 * 8, 120]));
+        let dir = std::env::temp_dir();
+        let ffmpeg = Path::new("ffmpeg");
+
+        let avif_path = dir.join(format!("rusty-gallery-{}-decode.avif", std::process::id()));
+        let mut avif = Vec::new();
+        DynamicImage::ImageRgb8(image.clone())
+            .write_with_encoder(AvifEncoder::new_with_speed_quality(&mut avif, 10, 100))
+            .unwrap();
+        std::fs::write(&avif_path, avif).unwrap();
+        let decoded = decode(&avif_path, ffmpeg);
+        std::fs::remove_file(&avif_path).unwrap();
+        assert_eq!(decoded.unwrap().dimensions(), (40, 30));
+
+        let jxl_path = dir.join(format!("rusty-gallery-{}-decode.jxl", std::process::id()));
+        let options = zune_core::options::EncoderOptions::new(
+            40,
+            30,
+            zune_core::colorspace::ColorSpace::RGB,
+            zune_core::bit_depth::BitDepth::Eight,
+        );
+        let mut jxl = Vec::new();
+        zune_jpegxl::JxlSimpleEncoder::new(image.as_raw(), options).encode(&mut jxl).unwrap();
+        std::fs::write(&jxl_path, jxl).unwrap();
+        let decoded = decode(&jxl_path, ffmpeg);
+        std::fs::remove_file(&jxl_path).unwrap();
+        // Lossless, so every pixel is back
+        assert_eq!(decoded.unwrap().to_rgb8(), image);
+    }
+
+    #[test]
+    fn pixel_cap() {
+        assert!(RenderSpec::new(Some(7680), Some(7680), RenderMode::Fill, (0.5, 0.5), None, 7680).is_err());
+        // The height follows a 1 × 1000 image: 7 680 000 pixels high
+        let img = DynamicImage::ImageRgb8(RgbImage::new(1, 1000));
+        let out = spec(Some(7680), None, RenderMode::Fit).apply(img);
+        assert!(out.width() <= 7680 && out.height() <= 7680);
+    }
+
+    #[test]
+    fn hanging_programs_are_killed() {
+        let started = Instant::now();
+        let output = output_within(Command::new("sleep").arg("30"), Duration::from_millis(200)).unwrap();
+        assert!(output.is_none());
+        assert!(started.elapsed() < Duration::from_secs(10));
+
+        let output = output_within(Command::new("echo").arg("frame"), Duration::from_secs(10)).unwrap().unwrap();
+        assert!(output.status.success());
+        assert_eq!(output.stdout, b"frame\n");
+    }
+}